@@ -0,0 +1,109 @@
+use std::io;
+
+use anyhow::Result;
+
+/// How worker threads are pinned to CPU cores, see
+/// [`ThreadPoolBuilder::pin_to_cores`](super::ThreadPoolBuilder::pin_to_cores).
+///
+/// CPU numbers are the ones used by the operating system, and only the CPUs
+/// the process is currently allowed to run on are considered.
+#[derive(Clone, Debug)]
+pub enum CoreSelection {
+  /// Worker `i` is pinned to the `i`-th available CPU, wrapping around when
+  /// there are more workers than CPUs.
+  Compact,
+
+  /// Workers are spread evenly over the available CPUs, in index order:
+  /// e.g. 4 workers on 8 CPUs are pinned to CPUs 0, 2, 4 and 6.
+  Scatter,
+
+  /// Worker `i` is pinned to the CPU set `sets[i % sets.len()]`.
+  Explicit(Vec<Vec<usize>>),
+}
+
+impl CoreSelection {
+  /// Resolves the selection into one CPU set per worker.
+  pub(super) fn resolve(&self, n_threads: usize) -> Result<Vec<Vec<usize>>> {
+    let available = available_cpus()?;
+    let n_cpus = available.len();
+    if n_cpus == 0 {
+      anyhow::bail!("no CPUs are available to pin worker threads to");
+    }
+
+    let sets = match self {
+      CoreSelection::Compact => (0..n_threads)
+        .map(|i| vec![available[i % n_cpus]])
+        .collect(),
+      CoreSelection::Scatter => (0..n_threads)
+        .map(|i| vec![available[i * n_cpus / n_threads]])
+        .collect(),
+      CoreSelection::Explicit(sets) => {
+        if sets.is_empty() {
+          anyhow::bail!("explicit core selection has no CPU sets");
+        }
+        for set in sets {
+          if set.is_empty() {
+            anyhow::bail!("explicit core selection contains an empty CPU set");
+          }
+          if let Some(cpu) = set.iter().find(|cpu| !available.contains(cpu)) {
+            anyhow::bail!("CPU {cpu} is not available to this process");
+          }
+        }
+        (0..n_threads)
+          .map(|i| sets[i % sets.len()].clone())
+          .collect()
+      }
+    };
+
+    Ok(sets)
+  }
+}
+
+/// Returns the CPUs the current thread is allowed to run on.
+#[cfg(target_os = "linux")]
+fn available_cpus() -> io::Result<Vec<usize>> {
+  unsafe {
+    let mut set: libc::cpu_set_t = std::mem::zeroed();
+    if libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(
+      (0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+        .collect(),
+    )
+  }
+}
+
+/// Restricts the current thread to run only on `cpus`.
+#[cfg(target_os = "linux")]
+pub(super) fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+  unsafe {
+    let mut set: libc::cpu_set_t = std::mem::zeroed();
+    for &cpu in cpus {
+      libc::CPU_SET(cpu, &mut set);
+    }
+    if libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available_cpus() -> io::Result<Vec<usize>> {
+  Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+  Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> io::Error {
+  io::Error::new(
+    io::ErrorKind::Unsupported,
+    "pinning worker threads is only supported on Linux",
+  )
+}
@@ -0,0 +1,156 @@
+use std::mem;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+use crossbeam_deque::Worker;
+
+use super::job::JobRef;
+use super::latch::AsCoreLatch;
+use super::latch::OnceLatch;
+use super::worker::WorkerThread;
+
+/// Runs `f`, which is expected to block (e.g. on I/O or a contended lock),
+/// without taking a worker away from the pool.
+///
+/// When called on a worker thread, the second halves of its joins that
+/// were not stealable yet in `JoinMode::Heartbeat` are pushed onto its
+/// deque, and a spare thread takes the worker's place until `f` returns:
+/// it steals the jobs the blocked worker left behind and helps with the
+/// rest of the pool's work. The spare thread of a worker is spawned by its
+/// first `block_in_place` and reused afterwards. Outside of the pool this
+/// just calls `f`.
+pub fn block_in_place<F, R>(f: F) -> R
+where F: FnOnce() -> R {
+  let worker_thread = WorkerThread::current();
+  if worker_thread.is_null() {
+    return f();
+  }
+
+  let _guard = BlockedGuard::new(unsafe { &*worker_thread });
+  f()
+}
+
+/// The thread that stands in for a worker while it is blocked in
+/// `block_in_place`. It has a slot of its own in the registry, so its
+/// deque can be stolen from and it can sleep like any worker.
+pub(super) struct SpareThread {
+  state: Mutex<SpareState>,
+
+  /// Signalled when the worker blocks while the spare thread is idle.
+  needed: Condvar,
+}
+
+enum SpareState {
+  /// No thread was spawned yet; holds the deques it will use.
+  NotSpawned {
+    worker: Worker<JobRef>,
+    pending: Worker<JobRef>,
+  },
+
+  /// The thread waits for the worker to block.
+  Idle,
+
+  /// The thread works for the pool until the latch is set.
+  Running(Arc<OnceLatch>),
+
+  /// The thread could not be spawned, so the worker is not replaced.
+  Unavailable,
+}
+
+impl SpareThread {
+  pub(super) fn new(worker: Worker<JobRef>, pending: Worker<JobRef>) -> SpareThread {
+    SpareThread {
+      state: Mutex::new(SpareState::NotSpawned { worker, pending }),
+      needed: Condvar::new(),
+    }
+  }
+
+  /// Puts the spare thread of `worker_thread` to work, spawning it first
+  /// if needed, and returns the latch that releases it.
+  fn activate(&self, worker_thread: &WorkerThread, spare_index: usize) -> Option<Arc<OnceLatch>> {
+    let release = Arc::new(OnceLatch::new());
+    let mut state = self.state.lock().unwrap();
+    match mem::replace(&mut *state, SpareState::Running(Arc::clone(&release))) {
+      SpareState::NotSpawned { worker, pending } => {
+        let registry = Arc::clone(worker_thread.registry());
+        if WorkerThread::new(worker, pending, registry, spare_index)
+          .spawn_spare()
+          .is_err()
+        {
+          *state = SpareState::Unavailable;
+          return None;
+        }
+      }
+      SpareState::Idle | SpareState::Running(_) => self.needed.notify_one(),
+      SpareState::Unavailable => {
+        *state = SpareState::Unavailable;
+        return None;
+      }
+    }
+    Some(release)
+  }
+
+  /// Called by the spare thread: waits until its worker blocks, then
+  /// returns the latch that is set once the worker is unblocked.
+  pub(super) fn wait_until_needed(&self) -> Arc<OnceLatch> {
+    let mut state = self.state.lock().unwrap();
+    loop {
+      if let SpareState::Running(release) = &*state
+        && !release.as_core_latch().probe()
+      {
+        return Arc::clone(release);
+      }
+      *state = SpareState::Idle;
+      state = self.needed.wait(state).unwrap();
+    }
+  }
+}
+
+/// Marks a worker as blocked, and has its spare thread stand in for it,
+/// for as long as it is alive.
+struct BlockedGuard<'w> {
+  /// `None` if the worker was already blocked by an outer call.
+  worker_thread: Option<&'w WorkerThread>,
+
+  /// Slot index and release latch of the running spare thread, if any.
+  spare: Option<(usize, Arc<OnceLatch>)>,
+}
+
+impl<'w> BlockedGuard<'w> {
+  fn new(worker_thread: &'w WorkerThread) -> Self {
+    if !worker_thread.set_blocked(true) {
+      return BlockedGuard {
+        worker_thread: None,
+        spare: None,
+      };
+    }
+
+    worker_thread.promote_pending();
+
+    // Spare threads have no spare of their own.
+    let registry = worker_thread.registry();
+    let spare = registry
+      .spare_thread(worker_thread.index())
+      .and_then(|(spare_index, spare)| {
+        let release = spare.activate(worker_thread, spare_index)?;
+        Some((spare_index, release))
+      });
+    BlockedGuard {
+      worker_thread: Some(worker_thread),
+      spare,
+    }
+  }
+}
+
+impl Drop for BlockedGuard<'_> {
+  fn drop(&mut self) {
+    if let Some(worker_thread) = self.worker_thread {
+      if let Some((spare_index, release)) = &self.spare {
+        let registry = worker_thread.registry();
+        unsafe { OnceLatch::set_and_tickle_one(&**release, registry, *spare_index) };
+      }
+      worker_thread.set_blocked(false);
+    }
+  }
+}
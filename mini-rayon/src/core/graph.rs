@@ -0,0 +1,232 @@
+use std::cell::UnsafeCell;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use super::job::Job;
+use super::job::JobRef;
+use super::latch::CountLatch;
+use super::latch::Latch;
+use super::registry::in_worker;
+use super::unwind;
+use super::worker::WorkerThread;
+
+type Task<'g> = Box<dyn FnOnce() + Send + 'g>;
+
+/// Identifies a task of a [`TaskGraph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+/// A set of tasks with dependencies between them, run in parallel on the
+/// thread pool.
+///
+/// A task starts once every task it depends on has finished. Tasks may
+/// borrow from the enclosing stack frame, since [`run`](TaskGraph::run)
+/// only returns once all of them are done.
+#[derive(Default)]
+pub struct TaskGraph<'g> {
+  tasks: Vec<Task<'g>>,
+  successors: Vec<Vec<usize>>,
+  num_dependencies: Vec<usize>,
+}
+
+impl<'g> TaskGraph<'g> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a task to the graph; it has no dependencies yet.
+  pub fn add_task<F>(&mut self, task: F) -> TaskId
+  where F: FnOnce() + Send + 'g {
+    self.tasks.push(Box::new(task));
+    self.successors.push(Vec::new());
+    self.num_dependencies.push(0);
+    TaskId(self.tasks.len() - 1)
+  }
+
+  /// Makes `task` wait for `dependency` to finish before it starts.
+  pub fn add_dependency(&mut self, task: TaskId, dependency: TaskId) {
+    assert!(
+      task.0 < self.tasks.len() && dependency.0 < self.tasks.len(),
+      "task id does not belong to this graph"
+    );
+    self.successors[dependency.0].push(task.0);
+    self.num_dependencies[task.0] += 1;
+  }
+
+  /// Runs every task of the graph and waits for all of them to finish.
+  ///
+  /// Tasks are started as soon as their dependencies are done; a task made
+  /// ready by another one is pushed onto the deque of the worker that ran
+  /// the latter. If a task panics, the tasks that have not started yet are
+  /// skipped and the panic is propagated once the running ones finish.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the dependencies form a cycle.
+  pub fn run(self) {
+    if self.tasks.is_empty() {
+      return;
+    }
+    assert!(self.is_acyclic(), "task graph contains a cycle");
+
+    let num_tasks = self.tasks.len();
+    in_worker(|worker_thread, _| unsafe {
+      let mut graph_run = GraphRun {
+        jobs: self
+          .tasks
+          .into_iter()
+          .zip(self.successors)
+          .zip(self.num_dependencies)
+          .map(|((task, successors), num_dependencies)| TaskJob {
+            graph_run: ptr::null(),
+            task: UnsafeCell::new(Some(task)),
+            pending: AtomicUsize::new(num_dependencies),
+            successors,
+          })
+          .collect(),
+        remaining: CountLatch::new(worker_thread, num_tasks),
+      };
+
+      let graph_run_ptr: *const GraphRun<'_, '_> = &graph_run;
+      for job in &mut graph_run.jobs {
+        job.graph_run = graph_run_ptr;
+      }
+
+      for job in &graph_run.jobs {
+        if job.pending.load(Ordering::Relaxed) == 0 {
+          worker_thread.push(JobRef::new(job));
+        }
+      }
+
+      graph_run.remaining.wait(worker_thread);
+    })
+  }
+
+  /// Checks that every task can eventually run, using Kahn's algorithm.
+  fn is_acyclic(&self) -> bool {
+    let mut pending = self.num_dependencies.clone();
+    let mut ready: Vec<usize> = (0..pending.len()).filter(|&i| pending[i] == 0).collect();
+    let mut visited = 0;
+
+    while let Some(task) = ready.pop() {
+      visited += 1;
+      for &successor in &self.successors[task] {
+        pending[successor] -= 1;
+        if pending[successor] == 0 {
+          ready.push(successor);
+        }
+      }
+    }
+
+    visited == self.tasks.len()
+  }
+}
+
+struct GraphRun<'g, 'r> {
+  jobs: Vec<TaskJob<'g, 'r>>,
+
+  /// Counts the tasks that have not finished yet.
+  remaining: CountLatch<'r>,
+}
+
+struct TaskJob<'g, 'r> {
+  graph_run: *const GraphRun<'g, 'r>,
+  task: UnsafeCell<Option<Task<'g>>>,
+
+  /// Number of dependencies that have not finished yet.
+  pending: AtomicUsize,
+  successors: Vec<usize>,
+}
+
+impl Job for TaskJob<'_, '_> {
+  unsafe fn execute(this: *const ()) {
+    unsafe {
+      let this = &*(this as *const Self);
+      let graph_run = &*this.graph_run;
+      let abort = unwind::AbortIfPanic;
+
+      let task = (*this.task.get()).take().unwrap();
+      if !graph_run.remaining.panicked()
+        && let Err(err) = unwind::halt_unwinding(task)
+      {
+        graph_run.remaining.record_panic(err);
+      }
+
+      let worker_thread = &*WorkerThread::current();
+      for &successor in &this.successors {
+        let successor = &graph_run.jobs[successor];
+        if successor.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+          worker_thread.push(JobRef::new(successor));
+        }
+      }
+
+      Latch::set(&graph_run.remaining);
+      mem::forget(abort);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::panic;
+  use std::sync::Mutex;
+  use std::sync::atomic::AtomicBool;
+  use std::sync::atomic::Ordering;
+
+  use super::TaskGraph;
+
+  #[test]
+  fn tasks_run_after_their_dependencies() {
+    let order = Mutex::new(Vec::new());
+    let log = &order;
+    let mut graph = TaskGraph::new();
+    let tasks: Vec<_> = (0..50)
+      .map(|i| graph.add_task(move || log.lock().unwrap().push(i)))
+      .collect();
+    let mut edges = Vec::new();
+    for i in 1..50 {
+      edges.push((i, i / 2));
+      if i % 3 == 0 {
+        edges.push((i, i - 1));
+      }
+    }
+    for &(task, dependency) in &edges {
+      graph.add_dependency(tasks[task], tasks[dependency]);
+    }
+    graph.run();
+
+    let order = order.into_inner().unwrap();
+    assert_eq!(order.len(), 50);
+    let position = |i| order.iter().position(|&j| j == i).unwrap();
+    for (task, dependency) in edges {
+      assert!(position(dependency) < position(task), "{task} ran before {dependency}");
+    }
+  }
+
+  #[test]
+  fn panicking_task_skips_its_dependents() {
+    let dependent_ran = AtomicBool::new(false);
+    let mut graph = TaskGraph::new();
+    let failing = graph.add_task(|| panic!("task failed"));
+    let dependent = graph.add_task(|| dependent_ran.store(true, Ordering::Relaxed));
+    graph.add_dependency(dependent, failing);
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| graph.run()));
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"task failed"));
+    assert!(!dependent_ran.load(Ordering::Relaxed));
+  }
+
+  #[test]
+  #[should_panic(expected = "cycle")]
+  fn cycle_is_rejected() {
+    let mut graph = TaskGraph::new();
+    let a = graph.add_task(|| ());
+    let b = graph.add_task(|| ());
+    graph.add_dependency(a, b);
+    graph.add_dependency(b, a);
+    graph.run();
+  }
+}
@@ -0,0 +1,49 @@
+use std::sync::Weak;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use super::registry::Registry;
+
+/// How `join_context` makes its second closure available to other workers,
+/// see [`ThreadPoolBuilder::join_mode`](super::ThreadPoolBuilder::join_mode).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JoinMode {
+  /// Every `join_context` pushes its second closure onto the worker's deque
+  /// right away, so it can be stolen while the first one runs.
+  #[default]
+  Eager,
+
+  /// `join_context` keeps its second closure private to the worker and runs
+  /// both sides sequentially. Every `interval`, an idle worker may take the
+  /// oldest pending second closure of each worker, even while that worker
+  /// is busy in a long leaf.
+  ///
+  /// This trades some latency in spreading work for not paying a deque
+  /// push and pop on every join, which pays off for very fine-grained
+  /// recursion.
+  Heartbeat(Duration),
+}
+
+/// Raises the heartbeat flag of every worker once per `interval`, until the
+/// registry goes away. Idle workers may be asleep by then, so one of them
+/// is woken up per worker with pending joins to promote.
+pub(super) fn heartbeat_loop(registry: Weak<Registry>, interval: Duration) {
+  loop {
+    thread::sleep(interval);
+
+    let Some(registry) = registry.upgrade() else {
+      return;
+    };
+    let mut num_pending = 0;
+    for info in &registry.thread_infos {
+      info.heartbeat.store(true, Ordering::Relaxed);
+      if !info.pending.is_empty() {
+        num_pending += 1;
+      }
+    }
+    if num_pending > 0 {
+      registry.sleep.new_injected_jobs(num_pending, true);
+    }
+  }
+}
@@ -96,6 +96,39 @@ where
   }
 }
 
+/// Represents a job stored in the heap. Used to hand work over to other
+/// threads when nobody waits on the job's stack frame. The job frees
+/// itself once executed.
+pub(super) struct HeapJob<BODY>
+where BODY: FnOnce() + Send
+{
+  job: BODY,
+}
+
+impl<BODY> HeapJob<BODY>
+where BODY: FnOnce() + Send
+{
+  pub(super) fn new(job: BODY) -> Box<Self> {
+    Box::new(HeapJob { job })
+  }
+
+  /// Creates a `JobRef` from this job -- note that this hides all
+  /// lifetimes, so it is up to you to ensure that this JobRef
+  /// doesn't outlive any data that it closes over.
+  pub(super) unsafe fn into_job_ref(self: Box<Self>) -> JobRef {
+    unsafe { JobRef::new(Box::into_raw(self)) }
+  }
+}
+
+impl<BODY> Job for HeapJob<BODY>
+where BODY: FnOnce() + Send
+{
+  unsafe fn execute(this: *const ()) {
+    let this = unsafe { Box::from_raw(this as *mut Self) };
+    (this.job)();
+  }
+}
+
 impl<T> JobResult<T> {
   fn call(func: impl FnOnce(bool) -> T) -> Self {
     match unwind::halt_unwinding(|| func(true)) {
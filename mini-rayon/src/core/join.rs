@@ -1,6 +1,9 @@
 use std::any::Any;
 use std::marker::PhantomData;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 
+use super::heartbeat::JoinMode;
 use super::unwind;
 use super::worker::WorkerThread;
 use crate::core::SpinLatch;
@@ -10,6 +13,9 @@ use crate::core::in_worker;
 
 pub struct FnContext {
   migrated: bool,
+  worker_index: usize,
+  depth: usize,
+  injected: bool,
 
   /// disable `Send` and `Sync`, just for a little future-proofing.
   _marker: PhantomData<*mut ()>,
@@ -17,9 +23,12 @@ pub struct FnContext {
 
 impl FnContext {
   #[inline]
-  pub fn new(migrated: bool) -> Self {
+  pub(crate) fn new(migrated: bool, worker_index: usize, depth: usize, injected: bool) -> Self {
     FnContext {
       migrated,
+      worker_index,
+      depth,
+      injected,
       _marker: PhantomData,
     }
   }
@@ -30,6 +39,27 @@ impl FnContext {
   pub fn migrated(&self) -> bool {
     self.migrated
   }
+
+  /// Returns the index of the worker thread running the closure.
+  #[inline]
+  pub fn worker_index(&self) -> usize {
+    self.worker_index
+  }
+
+  /// Returns how many `join_context` calls the closure is nested in,
+  /// starting at 1 for the closures of an outermost call. Closures that
+  /// were stolen keep the depth they were created at.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// Returns `true` if the `join_context` call came from outside the pool
+  /// and was injected into it.
+  #[inline]
+  pub fn injected(&self) -> bool {
+    self.injected
+  }
 }
 
 pub fn join_context<A, B, RA, RB>(op_a: A, op_b: B) -> (RA, RB)
@@ -40,22 +70,68 @@ where
   RB: Send,
 {
   #[inline]
-  fn call_a<R>(f: impl FnOnce(FnContext) -> R, injected: bool) -> impl FnOnce() -> R {
-    move || f(FnContext::new(injected))
+  fn call_a<R>(
+    f: impl FnOnce(FnContext) -> R,
+    worker_thread: &WorkerThread,
+    depth: usize,
+    injected: bool,
+  ) -> impl FnOnce() -> R {
+    move || {
+      let context = FnContext::new(injected, worker_thread.index(), depth, injected);
+      worker_thread.with_join_depth(depth, || f(context))
+    }
   }
 
   #[inline]
-  fn call_b<R>(f: impl FnOnce(FnContext) -> R) -> impl FnOnce(bool) -> R {
-    move |migrated| f(FnContext::new(migrated))
+  fn call_b<R>(
+    f: impl FnOnce(FnContext) -> R,
+    depth: usize,
+    injected: bool,
+  ) -> impl FnOnce(bool) -> R {
+    move |migrated| {
+      // `job_b` may have been stolen, so look up whoever is running it.
+      let worker_thread = unsafe { &*WorkerThread::current() };
+      let context = FnContext::new(migrated, worker_thread.index(), depth, injected);
+      worker_thread.with_join_depth(depth, || f(context))
+    }
   }
 
   in_worker(|worker_thread, injected| unsafe {
-    let job_b = StackJob::new(call_b(op_b), SpinLatch::new(worker_thread));
+    let depth = worker_thread.join_depth() + 1;
+
+    if worker_thread.join_nesting_exceeded() {
+      // Too deep already: don't add another stealable `StackJob` to this
+      // stack, just behave like a sequential call.
+      let result_a = call_a(op_a, worker_thread, depth, injected)();
+      let result_b = call_b(op_b, depth, injected)(injected);
+      return (result_a, result_b);
+    }
+    let _nesting = worker_thread.enter_join();
+
+    let job_b = StackJob::new(
+      call_b(op_b, depth, injected),
+      SpinLatch::new(worker_thread),
+    );
     let job_b_ref = job_b.as_job_ref();
     // let job_b_id = job_b_ref.id();
-    worker_thread.push(job_b_ref.clone());
+    match worker_thread.registry().join_mode() {
+      JoinMode::Eager => worker_thread.push(job_b_ref.clone()),
+      JoinMode::Heartbeat(_) => worker_thread.push_pending(job_b_ref.clone()),
+    }
+
+    let status_a = halt_unwinding(call_a(op_a, worker_thread, depth, injected));
+
+    if worker_thread.reclaim_pending(&job_b_ref) {
+      // No heartbeat promoted `job_b`, so nobody else can see it: just run
+      // it ourselves, like a plain sequential call would.
+      let result_a = match status_a {
+        Ok(v) => v,
+        Err(err) => unwind::resume_unwinding(err),
+      };
+      let result_b = job_b.run_inline(injected);
+      return (result_a, result_b);
+    }
 
-    let status_a = halt_unwinding(call_a(op_a, injected));
     let result_a = match status_a {
       Ok(v) => v,
       Err(err) => join_recover_from_panic(worker_thread, &job_b.latch, err),
@@ -85,6 +161,105 @@ where
   })
 }
 
+/// Runs three closures in parallel and returns their results.
+pub fn join3<A, B, C, RA, RB, RC>(op_a: A, op_b: B, op_c: C) -> (RA, RB, RC)
+where
+  A: FnOnce() -> RA + Send,
+  B: FnOnce() -> RB + Send,
+  C: FnOnce() -> RC + Send,
+  RA: Send,
+  RB: Send,
+  RC: Send,
+{
+  let (result_a, (result_b, result_c)) =
+    join_context(|_| op_a(), |_| join_context(|_| op_b(), |_| op_c()));
+  (result_a, result_b, result_c)
+}
+
+/// Runs every closure of `ops` in parallel and returns their results, in
+/// the same order.
+///
+/// The closures are split in halves recursively, so that the nesting of
+/// `join_context` stays logarithmic in their number.
+pub fn join_all<F, R>(mut ops: Vec<F>) -> Vec<R>
+where
+  F: FnOnce() -> R + Send,
+  R: Send,
+{
+  if ops.len() <= 1 {
+    return ops.into_iter().map(|op| op()).collect();
+  }
+
+  let right = ops.split_off(ops.len() / 2);
+  let (mut results, right_results) = join_context(|_| join_all(ops), |_| join_all(right));
+  results.extend(right_results);
+  results
+}
+
+/// Runs any number of closures in parallel and returns a tuple of their
+/// results, e.g. `join!(|| a(), || b(), || c(), || d())`.
+#[macro_export]
+macro_rules! join {
+  ($($op:expr),+ $(,)?) => {
+    $crate::join!(@bind [] [$($op),+])
+  };
+
+  // Give each closure a binding for its result. Hygiene keeps the `result`
+  // of each expansion distinct.
+  (@bind [$($bound:tt)*] [$op:expr $(, $rest:expr)*]) => {
+    $crate::join!(@bind [$($bound)* (result $op)] [$($rest),*])
+  };
+  (@bind [$(($result:ident $op:expr))*] []) => {{
+    let $crate::join!(@pattern $($result)*) = $crate::join!(@nest $($op),*);
+    ($($result,)*)
+  }};
+
+  (@pattern $result:ident) => {
+    $result
+  };
+  (@pattern $result:ident $($rest:ident)+) => {
+    ($result, $crate::join!(@pattern $($rest)+))
+  };
+
+  (@nest $op:expr) => {
+    ($op)()
+  };
+  (@nest $op:expr, $($rest:expr),+) => {
+    $crate::join_context(|_| ($op)(), |_| $crate::join!(@nest $($rest),+))
+  };
+}
+
+/// Runs two fallible closures in parallel, like `join_context`, and
+/// returns both results or the first error.
+///
+/// If `op_a` fails before `op_b` has started, `op_b` is skipped. An `op_b`
+/// that is already running is not interrupted, and when both fail the
+/// error of `op_a` is returned.
+pub fn try_join<A, B, RA, RB, E>(op_a: A, op_b: B) -> Result<(RA, RB), E>
+where
+  A: FnOnce() -> Result<RA, E> + Send,
+  B: FnOnce() -> Result<RB, E> + Send,
+  RA: Send,
+  RB: Send,
+  E: Send,
+{
+  let failed = AtomicBool::new(false);
+  let (result_a, result_b) = join_context(
+    |_| {
+      let result_a = op_a();
+      if result_a.is_err() {
+        failed.store(true, Ordering::Relaxed);
+      }
+      result_a
+    },
+    |_| (!failed.load(Ordering::Relaxed)).then(op_b),
+  );
+
+  let result_a = result_a?;
+  let result_b = result_b.expect("op_b is only skipped after op_a failed")?;
+  Ok((result_a, result_b))
+}
+
 #[cold] // cold path
 unsafe fn join_recover_from_panic(
   worker_thread: &WorkerThread,
@@ -1,12 +1,15 @@
+use std::any::Any;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 
 use super::registry::Registry;
+use super::unwind;
 use super::worker::WorkerThread;
 
 pub trait Latch {
@@ -179,6 +182,20 @@ impl OnceLatch {
       core_latch: CoreLatch::new(),
     }
   }
+
+  /// Set the latch, then tickle the specific worker thread,
+  /// which should be the one that owns this latch.
+  pub(super) unsafe fn set_and_tickle_one(
+    this: *const Self,
+    registry: &Registry,
+    target_worker_index: usize,
+  ) {
+    unsafe {
+      if CoreLatch::set(&(*this).core_latch) {
+        registry.notify_worker_latch_is_set(target_worker_index);
+      }
+    }
+  }
 }
 
 impl AsCoreLatch for OnceLatch {
@@ -188,6 +205,71 @@ impl AsCoreLatch for OnceLatch {
   }
 }
 
+/// A latch that is set once a count of outstanding jobs drops to zero, for
+/// operations that wait on a worker until all of their jobs are done. It
+/// also keeps the first panic of those jobs, so that the waiting worker
+/// can propagate it.
+///
+/// `CountLatch`es live on the stack of the waiting worker, which may free
+/// it as soon as the count hits zero. So the `set` that brings the count to
+/// zero must be the last access of its job to the latch, and to anything
+/// else owned by the waiting worker.
+pub(super) struct CountLatch<'r> {
+  counter: AtomicUsize,
+  panicked: AtomicBool,
+  panic: Mutex<Option<Box<dyn Any + Send>>>,
+  latch: SpinLatch<'r>,
+}
+
+impl<'r> CountLatch<'r> {
+  pub(super) fn new(thread: &'r WorkerThread, count: usize) -> CountLatch<'r> {
+    CountLatch {
+      counter: AtomicUsize::new(count),
+      panicked: AtomicBool::new(false),
+      panic: Mutex::new(None),
+      latch: SpinLatch::new(thread),
+    }
+  }
+
+  /// Adds an outstanding job; must be called before the count may have
+  /// dropped to zero.
+  pub(super) fn increment(&self) {
+    self.counter.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Keeps `err` unless an earlier panic was recorded.
+  pub(super) fn record_panic(&self, err: Box<dyn Any + Send>) {
+    self.panicked.store(true, Ordering::Relaxed);
+    self.panic.lock().unwrap().get_or_insert(err);
+  }
+
+  /// Returns `true` if one of the jobs panicked, in which case the ones
+  /// that have not started yet should be skipped.
+  pub(super) fn panicked(&self) -> bool {
+    self.panicked.load(Ordering::Relaxed)
+  }
+
+  /// Waits until the count drops to zero, executing jobs meanwhile, then
+  /// resumes the first recorded panic, if any.
+  pub(super) unsafe fn wait(&self, thread: &WorkerThread) {
+    unsafe { thread.wait_until(&self.latch) };
+    if let Some(err) = self.panic.lock().unwrap().take() {
+      unwind::resume_unwinding(err);
+    }
+  }
+}
+
+impl Latch for CountLatch<'_> {
+  #[inline]
+  unsafe fn set(this: *const Self) {
+    unsafe {
+      if (*this).counter.fetch_sub(1, Ordering::AcqRel) == 1 {
+        Latch::set(&(*this).latch);
+      }
+    }
+  }
+}
+
 pub struct LatchRef<'a, L> {
   inner: *const L,
   marker: PhantomData<&'a L>,
@@ -1,15 +1,41 @@
+mod affinity;
+mod blocking;
 mod counter;
+mod graph;
+mod heartbeat;
 mod job;
 mod join;
 mod latch;
+mod pipeline;
 mod registry;
 mod sleep;
+mod topology;
 mod unwind;
+mod waiter;
 mod worker;
+mod worker_local;
+mod worklist;
 
+pub use affinity::CoreSelection;
+pub use blocking::block_in_place;
+pub use graph::TaskGraph;
+pub use graph::TaskId;
+pub use heartbeat::JoinMode;
 pub use job::StackJob;
+pub use join::FnContext;
+pub use join::join_all;
 pub use join::join_context;
+pub use join::join3;
+pub use join::try_join;
 pub use latch::SpinLatch;
+pub use pipeline::Pipeline;
+pub use registry::ThreadPoolBuilder;
 pub use registry::current_num_threads;
+pub use registry::current_thread_index;
 pub use registry::in_worker;
+pub use topology::WorkerGroups;
 pub use unwind::halt_unwinding;
+pub use worker_local::WorkerLocal;
+pub use worklist::Pusher;
+pub use worklist::worklist;
+pub(crate) use waiter::WaitQueue;
@@ -0,0 +1,515 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use super::job::HeapJob;
+use super::job::JobRef;
+use super::latch::CountLatch;
+use super::latch::Latch;
+use super::registry::in_worker;
+use super::unwind;
+use super::worker::WorkerThread;
+
+/// A chain of stages that a stream of items flows through, run on the
+/// thread pool.
+///
+/// Parallel stages process any number of items at once. Serial stages
+/// process one item at a time, either in the order the items came out of
+/// the input (`serial_in_order`) or in whatever order they reach the stage
+/// (`serial_out_of_order`). The number of items in flight is bounded by the
+/// token count passed to [`run`](Pipeline::run).
+///
+/// Every stage is a type of its own in `S`, so items are passed from one
+/// stage to the next as they are, without boxing them.
+///
+/// ```ignore
+/// Pipeline::new()
+///   .parallel(|line: String| parse(&line))
+///   .serial_in_order(|record| writer.write(record))
+///   .run(16, lines);
+/// ```
+pub struct Pipeline<In, S = NoStages> {
+  stages: S,
+  _marker: PhantomData<fn(In)>,
+}
+
+/// A chain of pipeline stages taking `In` items, see [`Pipeline`].
+pub trait Stages<In>: Sync {
+  type Out;
+
+  /// Number of stages in the chain.
+  const LEN: usize;
+
+  /// Moves an item through the stages, then hands it to `next`. Returns
+  /// `false` if the item had to be parked at a busy serial stage.
+  fn process(
+    &self,
+    cx: &RunContext<'_>,
+    seq: u64,
+    item: Option<In>,
+    next: &Next<'_, Self::Out>,
+  ) -> bool;
+
+  /// Moves the next item parked at the stage at `index` through the rest
+  /// of the stages, like `process`.
+  fn resume(&self, cx: &RunContext<'_>, index: usize, next: &Next<'_, Self::Out>) -> bool;
+}
+
+/// A single pipeline stage taking `In` items.
+pub trait Stage<In>: Sync {
+  type Out;
+
+  /// See `Stages::process`; `index` is the index of this stage.
+  fn process(
+    &self,
+    cx: &RunContext<'_>,
+    index: usize,
+    seq: u64,
+    item: Option<In>,
+    next: &Next<'_, Self::Out>,
+  ) -> bool;
+
+  /// See `Stages::resume`.
+  fn resume(&self, cx: &RunContext<'_>, index: usize, next: &Next<'_, Self::Out>) -> bool;
+}
+
+/// What happens to an item once it is through some stages: the rest of the
+/// stages. Items are `None` once they were dropped after a panic.
+type Next<'n, T> = dyn Fn(u64, Option<T>) -> bool + 'n;
+
+/// `Pipeline` with a parallel stage calling `F` added after the stages `S`.
+type WithParallel<In, S, F, U> =
+  Pipeline<In, Then<S, ParallelStage<F, <S as Stages<In>>::Out, U>>>;
+
+/// `Pipeline` with a serial stage calling `F` added after the stages `S`.
+type WithSerial<In, S, F, U> = Pipeline<In, Then<S, SerialStage<F, <S as Stages<In>>::Out, U>>>;
+
+/// The stages of a new pipeline: none at all.
+pub struct NoStages;
+
+/// The stages `prev`, followed by `stage`.
+pub struct Then<P, S> {
+  prev: P,
+  stage: S,
+}
+
+pub struct ParallelStage<F, T, U> {
+  func: F,
+  _marker: PhantomData<fn(T) -> U>,
+}
+
+pub struct SerialStage<F, T, U> {
+  func: Mutex<F>,
+  in_order: bool,
+  state: Mutex<SerialState<T>>,
+  _marker: PhantomData<fn(T) -> U>,
+}
+
+struct SerialState<T> {
+  /// An item is being processed by the stage, or handed over to a job that
+  /// resumes it.
+  busy: bool,
+
+  /// Sequence number of the next item an in-order stage may process.
+  next_seq: u64,
+
+  /// Items that reached the stage while it was busy, or too early.
+  /// `None` stands for an item that was dropped after a panic.
+  buffer: BTreeMap<u64, Option<T>>,
+}
+
+/// What the stages need from the running pipeline.
+pub struct RunContext<'a> {
+  live_tokens: &'a CountLatch<'a>,
+
+  /// Pushes a job that resumes the next item parked at the stage at the
+  /// given index.
+  resume_at: &'a dyn Fn(usize),
+}
+
+impl<T> Default for Pipeline<T> {
+  fn default() -> Self {
+    Pipeline {
+      stages: NoStages,
+      _marker: PhantomData,
+    }
+  }
+}
+
+impl<T> Pipeline<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl<In, S> Pipeline<In, S>
+where S: Stages<In>
+{
+  /// Adds a stage that may process any number of items at the same time.
+  pub fn parallel<U, F>(self, func: F) -> WithParallel<In, S, F, U>
+  where
+    F: Fn(S::Out) -> U + Send + Sync,
+    U: Send,
+  {
+    self.push_stage(ParallelStage {
+      func,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Adds a stage that processes one item at a time, in input order.
+  pub fn serial_in_order<U, F>(self, func: F) -> WithSerial<In, S, F, U>
+  where
+    F: FnMut(S::Out) -> U + Send,
+    S::Out: Send,
+    U: Send,
+  {
+    self.push_stage(SerialStage::new(func, true))
+  }
+
+  /// Adds a stage that processes one item at a time, in any order.
+  pub fn serial_out_of_order<U, F>(self, func: F) -> WithSerial<In, S, F, U>
+  where
+    F: FnMut(S::Out) -> U + Send,
+    S::Out: Send,
+    U: Send,
+  {
+    self.push_stage(SerialStage::new(func, false))
+  }
+
+  fn push_stage<T>(self, stage: T) -> Pipeline<In, Then<S, T>>
+  where T: Stage<S::Out> {
+    Pipeline {
+      stages: Then {
+        prev: self.stages,
+        stage,
+      },
+      _marker: PhantomData,
+    }
+  }
+
+  /// Feeds every item of `input` through the stages and waits until all of
+  /// them are done, with at most `max_tokens` items in flight at once.
+  ///
+  /// The input is read serially. The output of the last stage is dropped.
+  /// If a stage panics, the items that are still in flight skip the
+  /// remaining stages, no more input is read, and the panic is propagated
+  /// once the pipeline drains.
+  pub fn run<I>(self, max_tokens: usize, input: I)
+  where
+    In: Send,
+    S: Send,
+    I: IntoIterator<Item = In>,
+    I::IntoIter: Send,
+  {
+    assert!(max_tokens > 0, "a pipeline needs at least one token");
+    let input = input.into_iter();
+
+    in_worker(|worker_thread, _| unsafe {
+      let pipeline_run = PipelineRun {
+        stages: self.stages,
+        input: Mutex::new(Input {
+          iter: input,
+          next_seq: 0,
+        }),
+        live_tokens: CountLatch::new(worker_thread, max_tokens),
+        _marker: PhantomData,
+      };
+
+      for _ in 0..max_tokens {
+        worker_thread.push(pipeline_run.token_job(None));
+      }
+
+      pipeline_run.live_tokens.wait(worker_thread);
+    })
+  }
+}
+
+impl<T> Stages<T> for NoStages {
+  type Out = T;
+
+  const LEN: usize = 0;
+
+  fn process(&self, _cx: &RunContext<'_>, seq: u64, item: Option<T>, next: &Next<'_, T>) -> bool {
+    next(seq, item)
+  }
+
+  fn resume(&self, _cx: &RunContext<'_>, _index: usize, _next: &Next<'_, T>) -> bool {
+    unreachable!("no stage to resume")
+  }
+}
+
+impl<In, P, S> Stages<In> for Then<P, S>
+where
+  P: Stages<In>,
+  S: Stage<P::Out>,
+{
+  type Out = S::Out;
+
+  const LEN: usize = P::LEN + 1;
+
+  fn process(
+    &self,
+    cx: &RunContext<'_>,
+    seq: u64,
+    item: Option<In>,
+    next: &Next<'_, S::Out>,
+  ) -> bool {
+    self.prev.process(cx, seq, item, &|seq, item| {
+      self.stage.process(cx, P::LEN, seq, item, next)
+    })
+  }
+
+  fn resume(&self, cx: &RunContext<'_>, index: usize, next: &Next<'_, S::Out>) -> bool {
+    if index == P::LEN {
+      return self.stage.resume(cx, index, next);
+    }
+    self.prev.resume(cx, index, &|seq, item| {
+      self.stage.process(cx, P::LEN, seq, item, next)
+    })
+  }
+}
+
+impl<F, T, U> Stage<T> for ParallelStage<F, T, U>
+where F: Fn(T) -> U + Sync
+{
+  type Out = U;
+
+  fn process(
+    &self,
+    cx: &RunContext<'_>,
+    _index: usize,
+    seq: u64,
+    item: Option<T>,
+    next: &Next<'_, U>,
+  ) -> bool {
+    next(seq, cx.call(item, &self.func))
+  }
+
+  fn resume(&self, _cx: &RunContext<'_>, _index: usize, _next: &Next<'_, U>) -> bool {
+    unreachable!("items are never parked at parallel stages")
+  }
+}
+
+impl<F, T, U> SerialStage<F, T, U> {
+  fn new(func: F, in_order: bool) -> Self {
+    SerialStage {
+      func: Mutex::new(func),
+      in_order,
+      state: Mutex::new(SerialState {
+        busy: false,
+        next_seq: 0,
+        buffer: BTreeMap::new(),
+      }),
+      _marker: PhantomData,
+    }
+  }
+}
+
+impl<F, T, U> SerialStage<F, T, U>
+where
+  F: FnMut(T) -> U + Send,
+  T: Send,
+{
+  /// Processes an item while the stage is held for it, then hands the
+  /// stage over to the next parked item, if any.
+  fn run_item(
+    &self,
+    cx: &RunContext<'_>,
+    index: usize,
+    seq: u64,
+    item: Option<T>,
+    next: &Next<'_, U>,
+  ) -> bool {
+    let item = cx.call(item, |item| (self.func.lock().unwrap())(item));
+
+    let resume = {
+      let mut state = self.state.lock().unwrap();
+      let resume = if self.in_order {
+        state.next_seq += 1;
+        state.buffer.contains_key(&state.next_seq)
+      } else {
+        !state.buffer.is_empty()
+      };
+      // Keep the stage for the parked item.
+      state.busy = resume;
+      resume
+    };
+
+    // Resume the parked item on another job, so that ours can keep going.
+    if resume {
+      (cx.resume_at)(index);
+    }
+    next(seq, item)
+  }
+}
+
+impl<F, T, U> Stage<T> for SerialStage<F, T, U>
+where
+  F: FnMut(T) -> U + Send,
+  T: Send,
+{
+  type Out = U;
+
+  fn process(
+    &self,
+    cx: &RunContext<'_>,
+    index: usize,
+    seq: u64,
+    item: Option<T>,
+    next: &Next<'_, U>,
+  ) -> bool {
+    {
+      let mut state = self.state.lock().unwrap();
+      if state.busy || (self.in_order && seq != state.next_seq) {
+        state.buffer.insert(seq, item);
+        return false;
+      }
+      state.busy = true;
+    }
+
+    self.run_item(cx, index, seq, item, next)
+  }
+
+  fn resume(&self, cx: &RunContext<'_>, index: usize, next: &Next<'_, U>) -> bool {
+    let (seq, item) = {
+      let mut state = self.state.lock().unwrap();
+      if self.in_order {
+        let seq = state.next_seq;
+        (seq, state.buffer.remove(&seq).unwrap())
+      } else {
+        state.buffer.pop_first().unwrap()
+      }
+    };
+
+    self.run_item(cx, index, seq, item, next)
+  }
+}
+
+impl RunContext<'_> {
+  /// Calls `func` on the item, unless it was dropped or a stage panicked.
+  fn call<T, U>(&self, item: Option<T>, func: impl FnOnce(T) -> U) -> Option<U> {
+    let item = item.filter(|_| !self.live_tokens.panicked())?;
+    match unwind::halt_unwinding(|| func(item)) {
+      Ok(item) => Some(item),
+      Err(err) => {
+        self.live_tokens.record_panic(err);
+        None
+      }
+    }
+  }
+}
+
+struct Input<I> {
+  iter: I,
+  next_seq: u64,
+}
+
+struct PipelineRun<'r, In, S, I> {
+  stages: S,
+  input: Mutex<Input<I>>,
+
+  /// Counts the tokens that have not been retired yet; a token is retired
+  /// once it finds the input exhausted.
+  live_tokens: CountLatch<'r>,
+
+  _marker: PhantomData<fn(In)>,
+}
+
+impl<In, S, I> PipelineRun<'_, In, S, I>
+where
+  In: Send,
+  S: Stages<In>,
+  I: Iterator<Item = In> + Send,
+{
+  /// Creates a job that carries one token through the pipeline, starting
+  /// with the item parked at the stage at `resume` if given, else with the
+  /// next input item.
+  unsafe fn token_job(&self, resume: Option<usize>) -> JobRef {
+    unsafe { HeapJob::new(move || self.drive_token(resume)).into_job_ref() }
+  }
+
+  fn drive_token(&self, mut resume: Option<usize>) {
+    let resume_at = |index| unsafe {
+      let worker_thread = &*WorkerThread::current();
+      worker_thread.push(self.token_job(Some(index)));
+    };
+    let cx = RunContext {
+      live_tokens: &self.live_tokens,
+      resume_at: &resume_at,
+    };
+    // The output of the last stage is dropped.
+    let done = |_: u64, _: Option<S::Out>| true;
+
+    loop {
+      let finished = match resume.take() {
+        Some(index) => self.stages.resume(&cx, index, &done),
+        None => match self.read_input() {
+          Some((seq, item)) => self.stages.process(&cx, seq, Some(item), &done),
+          None => break,
+        },
+      };
+
+      if !finished {
+        // The item is parked at a serial stage, and its token with it.
+        return;
+      }
+    }
+
+    unsafe { Latch::set(&self.live_tokens) };
+  }
+
+  fn read_input(&self) -> Option<(u64, In)> {
+    if self.live_tokens.panicked() {
+      return None;
+    }
+
+    let mut input = self.input.lock().unwrap();
+    match unwind::halt_unwinding(|| input.iter.next()) {
+      Ok(item) => {
+        let seq = input.next_seq;
+        input.next_seq += 1;
+        item.map(|item| (seq, item))
+      }
+      Err(err) => {
+        self.live_tokens.record_panic(err);
+        None
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::panic;
+
+  use super::Pipeline;
+
+  #[test]
+  fn in_order_stage_sees_input_order() {
+    for max_tokens in [1, 2, 8, 64] {
+      let mut output = Vec::new();
+      Pipeline::new()
+        .parallel(|x: u64| x * 2)
+        .serial_out_of_order(|x| x + 1)
+        .serial_in_order(|x| output.push(x))
+        .run(max_tokens, 0..1000);
+      let expected: Vec<u64> = (0..1000).map(|x| x * 2 + 1).collect();
+      assert_eq!(output, expected, "with {max_tokens} tokens");
+    }
+  }
+
+  #[test]
+  fn panicking_stage_is_propagated() {
+    let mut seen = 0;
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+      Pipeline::new()
+        .parallel(|x: u32| if x == 50 { panic!("stage failed") } else { x })
+        .serial_in_order(|_| seen += 1)
+        .run(4, 0..1000);
+    }));
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"stage failed"));
+    assert!(seen < 1000);
+  }
+}
@@ -1,6 +1,10 @@
-use std::ptr;
+use std::io;
 use std::sync::Arc;
-use std::sync::Once;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::PoisonError;
+use std::sync::atomic::AtomicBool;
+use std::thread;
 
 use anyhow::Result;
 use crossbeam_deque::Injector;
@@ -8,26 +12,124 @@ use crossbeam_deque::Steal;
 use crossbeam_deque::Stealer;
 use crossbeam_deque::Worker;
 
+use super::affinity::CoreSelection;
+use super::blocking::SpareThread;
+use super::heartbeat;
+use super::heartbeat::JoinMode;
 use super::job::JobRef;
 use super::latch::LockLatch;
 use super::latch::OnceLatch;
 use super::sleep::Sleep;
+use super::topology::WorkerGroups;
 use super::worker::WorkerThread;
 use crate::core::job::StackJob;
 use crate::core::latch::LatchRef;
 
 pub struct ThreadPoolBuilder {
   num_threads: usize,
+
+  core_selection: Option<CoreSelection>,
+
+  worker_groups: Option<WorkerGroups>,
+
+  join_mode: JoinMode,
+
+  max_join_nesting: Option<usize>,
+
+  stack_size: Option<usize>,
 }
 
 impl Default for ThreadPoolBuilder {
   fn default() -> Self {
-    ThreadPoolBuilder { num_threads: 10 }
+    ThreadPoolBuilder {
+      num_threads: 10,
+      core_selection: None,
+      worker_groups: None,
+      join_mode: JoinMode::Eager,
+      max_join_nesting: None,
+      stack_size: None,
+    }
   }
 }
 
-static mut THE_REGISTRY: Option<Arc<Registry>> = None;
-static THE_REGISTRY_SET: Once = Once::new();
+impl ThreadPoolBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the number of worker threads in the pool. Building the pool fails
+  /// if this is 0.
+  pub fn num_threads(mut self, num_threads: usize) -> Self {
+    self.num_threads = num_threads;
+    self
+  }
+
+  /// Pins every worker thread to the CPU set chosen by `selection`.
+  ///
+  /// The affinity is applied by each worker when it starts; if it cannot be
+  /// applied, building the pool fails with the error of the first worker
+  /// that could not be pinned.
+  pub fn pin_to_cores(mut self, selection: CoreSelection) -> Self {
+    self.core_selection = Some(selection);
+    self
+  }
+
+  /// Groups the worker threads so that stealing prefers victims of the
+  /// same group, e.g. workers sharing a socket or a last level cache.
+  ///
+  /// Groups read from `/sys/devices/system/cpu` require the workers to be
+  /// pinned with [`pin_to_cores`](Self::pin_to_cores).
+  pub fn worker_groups(mut self, groups: WorkerGroups) -> Self {
+    self.worker_groups = Some(groups);
+    self
+  }
+
+  /// Selects how `join_context` schedules its second closure, see
+  /// [`JoinMode`].
+  pub fn join_mode(mut self, join_mode: JoinMode) -> Self {
+    self.join_mode = join_mode;
+    self
+  }
+
+  /// Limits how many `join_context` calls may be nested on a worker's
+  /// stack. Past the limit, `join_context` runs both closures inline, one
+  /// after the other, and nothing below it can be stolen.
+  ///
+  /// A worker waiting for a stolen closure runs other jobs on top of its
+  /// stack, so deeply unbalanced recursion can pile up many `StackJob`s on
+  /// it; this bounds how many of them can be stolen. It does not bound the
+  /// recursion of the closures themselves, which still runs on the same
+  /// stack: use [`stack_size`](Self::stack_size) for deep recursion.
+  pub fn max_join_nesting(mut self, max_join_nesting: usize) -> Self {
+    self.max_join_nesting = Some(max_join_nesting);
+    self
+  }
+
+  /// Sets the stack size, in bytes, of the worker threads, instead of the
+  /// default of `std::thread`. Deeply recursive `join_context` calls need
+  /// a larger stack than the default 2 MiB.
+  pub fn stack_size(mut self, stack_size: usize) -> Self {
+    self.stack_size = Some(stack_size);
+    self
+  }
+
+  /// Initializes the global thread pool with this configuration.
+  ///
+  /// Fails if the global pool has already been initialized, either by an
+  /// earlier call or implicitly by running parallel work. If building the
+  /// pool fails, e.g. because its workers could not be pinned, the global
+  /// pool is left uninitialized, so that a later call or the default pool
+  /// can still set it up.
+  pub fn build_global(self) -> Result<()> {
+    set_global_registry(|| Registry::new(&self)).map(|_| ())
+  }
+}
+
+static THE_REGISTRY: OnceLock<Arc<Registry>> = OnceLock::new();
+
+/// Held while building the global registry, which is only stored in
+/// `THE_REGISTRY` once it was built successfully.
+static THE_REGISTRY_INIT: Mutex<()> = Mutex::new(());
 
 pub struct ThreadInfo {
   pub(crate) primed: LockLatch,
@@ -37,51 +139,160 @@ pub struct ThreadInfo {
   pub(crate) terminate: OnceLatch,
 
   pub(crate) stealer: Stealer<JobRef>,
+
+  /// CPUs this worker pins itself to when it starts, if any.
+  pub(crate) cpus: Option<Vec<usize>>,
+
+  /// Set by the worker if pinning itself to `cpus` failed.
+  pub(crate) pin_error: Mutex<Option<io::Error>>,
+
+  /// Stealing group of this worker, see `ThreadPoolBuilder::worker_groups`.
+  pub(crate) group: usize,
+
+  /// Raised periodically in `JoinMode::Heartbeat`, allowing an idle worker
+  /// to promote the oldest pending join of this worker.
+  pub(crate) heartbeat: AtomicBool,
+
+  /// Takes the oldest pending join of this worker, to promote it.
+  pub(crate) pending: Stealer<JobRef>,
 }
 
 impl ThreadInfo {
-  fn new(stealer: Stealer<JobRef>) -> ThreadInfo {
+  fn new(
+    stealer: Stealer<JobRef>,
+    pending: Stealer<JobRef>,
+    cpus: Option<Vec<usize>>,
+    group: usize,
+  ) -> ThreadInfo {
     ThreadInfo {
       primed: LockLatch::new(),
       stopped: LockLatch::new(),
       terminate: OnceLatch::new(),
       stealer,
+      cpus,
+      pin_error: Mutex::new(None),
+      group,
+      heartbeat: AtomicBool::new(false),
+      pending,
     }
   }
 }
 
 pub struct Registry {
+  /// The workers, followed by the slots of their spare threads: the spare
+  /// of worker `i` is at `num_threads + i`, see `block_in_place`.
   pub thread_infos: Vec<ThreadInfo>,
+  num_threads: usize,
+  spare_threads: Vec<SpareThread>,
   injected_jobs: Injector<JobRef>,
   pub sleep: Sleep,
+  join_mode: JoinMode,
+  max_join_nesting: Option<usize>,
+  stack_size: Option<usize>,
 }
 
 impl Registry {
   pub fn new(builder: &ThreadPoolBuilder) -> Result<Arc<Registry>> {
     let n_threads = builder.num_threads;
+    if n_threads == 0 {
+      anyhow::bail!("a thread pool needs at least one worker thread");
+    }
 
-    let (workers, stealers): (Vec<_>, Vec<_>) = (0..n_threads)
+    let (mut workers, stealers): (Vec<_>, Vec<_>) = (0..2 * n_threads)
       .map(|_| {
         let worker = Worker::new_fifo();
-        let stealer = worker.stealer();
-        (worker, stealer)
+        let pending = Worker::new_lifo();
+        let stealers = (worker.stealer(), pending.stealer());
+        ((worker, pending), stealers)
       })
       .unzip();
-
+    let spare_workers = workers.split_off(n_threads);
+
+    let cpu_sets: Vec<Option<Vec<usize>>> = match &builder.core_selection {
+      Some(selection) => selection
+        .resolve(n_threads)?
+        .into_iter()
+        .map(Some)
+        .collect(),
+      None => vec![None; n_threads],
+    };
+
+    let groups = match &builder.worker_groups {
+      Some(groups) => groups.resolve(&cpu_sets)?,
+      None => vec![0; n_threads],
+    };
+
+    // A spare thread runs on the CPUs, and in the group, of its worker.
     let registry = Arc::new(Registry {
-      thread_infos: stealers.into_iter().map(ThreadInfo::new).collect(),
+      thread_infos: stealers
+        .into_iter()
+        .zip(cpu_sets.iter().cycle().cloned())
+        .zip(groups.iter().cycle().copied())
+        .map(|(((stealer, pending), cpus), group)| ThreadInfo::new(stealer, pending, cpus, group))
+        .collect(),
+      num_threads: n_threads,
+      spare_threads: spare_workers
+        .into_iter()
+        .map(|(worker, pending)| SpareThread::new(worker, pending))
+        .collect(),
       injected_jobs: Injector::new(),
-      sleep: Sleep::new(n_threads),
+      sleep: Sleep::new(2 * n_threads),
+      join_mode: builder.join_mode,
+      max_join_nesting: builder.max_join_nesting,
+      stack_size: builder.stack_size,
     });
 
-    for (index, worker) in workers.into_iter().enumerate() {
-      let worker = WorkerThread::new(worker, Arc::clone(&registry), index);
+    for (index, (worker, pending)) in workers.into_iter().enumerate() {
+      let worker = WorkerThread::new(worker, pending, Arc::clone(&registry), index);
 
       worker.spawn()?;
     }
+
+    if builder.core_selection.is_some() {
+      registry.check_pinned()?;
+    }
+
+    if let JoinMode::Heartbeat(interval) = builder.join_mode {
+      let registry = Arc::downgrade(&registry);
+      thread::Builder::new().spawn(move || heartbeat::heartbeat_loop(registry, interval))?;
+    }
     Ok(registry)
   }
 
+  /// Waits for every worker to start and reports the first one that could
+  /// not pin itself to its CPUs, shutting the workers down in that case.
+  fn check_pinned(&self) -> Result<()> {
+    let workers = &self.thread_infos[..self.num_threads];
+    for info in workers {
+      info.primed.wait_and_reset();
+    }
+
+    let failed = workers
+      .iter()
+      .enumerate()
+      .find_map(|(index, info)| {
+        let err = info.pin_error.lock().unwrap().take()?;
+        Some((index, info.cpus.clone().unwrap_or_default(), err))
+      });
+
+    match failed {
+      None => Ok(()),
+      Some((index, cpus, err)) => {
+        self.terminate();
+        Err(anyhow::Error::new(err).context(format!(
+          "failed to pin worker thread {index} to CPUs {cpus:?}"
+        )))
+      }
+    }
+  }
+
+  /// Tells every worker to exit once it runs out of work.
+  fn terminate(&self) {
+    for (index, info) in self.thread_infos.iter().enumerate() {
+      unsafe { OnceLatch::set_and_tickle_one(&info.terminate, self, index) };
+    }
+  }
+
   pub fn has_injected_job(&self) -> bool {
     !self.injected_jobs.is_empty()
   }
@@ -89,6 +300,7 @@ impl Registry {
   fn inject(&self, injected_job: JobRef) {
     let queue_was_empty = self.injected_jobs.is_empty();
     self.injected_jobs.push(injected_job);
+    self.sleep.new_injected_jobs(1, queue_was_empty);
   }
 
   pub fn in_worker<OP, R>(&self, op: OP) -> R
@@ -131,7 +343,39 @@ impl Registry {
   }
 
   pub fn num_threads(&self) -> usize {
-    self.thread_infos.len()
+    self.num_threads
+  }
+
+  /// Returns the slot index and the spare thread of the worker at `index`,
+  /// or `None` if that is a spare thread itself.
+  pub(super) fn spare_thread(&self, index: usize) -> Option<(usize, &SpareThread)> {
+    let spare = self.spare_threads.get(index)?;
+    Some((self.num_threads + index, spare))
+  }
+
+  pub fn join_mode(&self) -> JoinMode {
+    self.join_mode
+  }
+
+  pub fn max_join_nesting(&self) -> Option<usize> {
+    self.max_join_nesting
+  }
+
+  pub fn stack_size(&self) -> Option<usize> {
+    self.stack_size
+  }
+
+  /// Returns the registry of the current worker thread, or the global one
+  /// when called from outside the pool.
+  pub fn current() -> Arc<Registry> {
+    unsafe {
+      let worker_thread = WorkerThread::current();
+      if worker_thread.is_null() {
+        Arc::clone(global_registry())
+      } else {
+        Arc::clone(&(*worker_thread).registry)
+      }
+    }
   }
 
   pub fn current_num_threads() -> usize {
@@ -166,25 +410,27 @@ fn default_global_registry() -> Result<Arc<Registry>> {
   result
 }
 
-fn set_global_registry() -> Result<&'static Arc<Registry>> {
-  let mut result = Err(anyhow::Error::msg("Global Worker Pool Already Initialized"));
-
-  THE_REGISTRY_SET.call_once(|| {
-    result = default_global_registry().map(|registry| unsafe {
-      ptr::addr_of_mut!(THE_REGISTRY).write(Some(registry));
-      (*ptr::addr_of!(THE_REGISTRY)).as_ref().unwrap_unchecked()
-    })
-  });
+fn set_global_registry<F>(registry: F) -> Result<&'static Arc<Registry>>
+where F: FnOnce() -> Result<Arc<Registry>> {
+  let _init = THE_REGISTRY_INIT
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner);
+  if THE_REGISTRY.get().is_some() {
+    return Err(anyhow::Error::msg("Global Worker Pool Already Initialized"));
+  }
 
-  result
+  // If building fails, the slot stays empty for a later attempt.
+  let registry = registry()?;
+  Ok(THE_REGISTRY.get_or_init(|| registry))
 }
 
 fn global_registry() -> &'static Arc<Registry> {
-  set_global_registry()
-    .or_else(|err| {
-      let registry = unsafe { &*ptr::addr_of!(THE_REGISTRY) };
-      registry.as_ref().ok_or(err)
-    })
+  if let Some(registry) = THE_REGISTRY.get() {
+    return registry;
+  }
+
+  set_global_registry(default_global_registry)
+    .or_else(|err| THE_REGISTRY.get().ok_or(err))
     .expect("The global thread pool has not been initialized.")
 }
 
@@ -206,3 +452,13 @@ where
 pub fn current_num_threads() -> usize {
   Registry::current_num_threads()
 }
+
+/// Returns the index of the current worker thread within its pool, or
+/// `None` if called from outside the pool.
+///
+/// A spare thread standing in for a worker blocked in
+/// [`block_in_place`](super::block_in_place) has the index of the worker
+/// plus the number of workers.
+pub fn current_thread_index() -> Option<usize> {
+  unsafe { WorkerThread::current().as_ref().map(WorkerThread::index) }
+}
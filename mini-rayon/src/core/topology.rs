@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+/// How worker threads are grouped for stealing, see
+/// [`ThreadPoolBuilder::worker_groups`](super::ThreadPoolBuilder::worker_groups).
+///
+/// A worker that runs out of work first tries to steal from the workers of
+/// its own group, and only then from the rest of the pool.
+#[derive(Clone, Debug)]
+pub enum WorkerGroups {
+  /// Groups of worker indices. Workers that are not listed form a group of
+  /// their own.
+  Explicit(Vec<Vec<usize>>),
+
+  /// Workers pinned to CPUs of the same physical package (socket) form a
+  /// group, as reported by `/sys/devices/system/cpu`.
+  Package,
+
+  /// Workers pinned to CPUs sharing the same last level cache form a group,
+  /// as reported by `/sys/devices/system/cpu`.
+  LastLevelCache,
+}
+
+impl WorkerGroups {
+  /// Resolves the grouping into the group index of each worker, given the
+  /// CPUs each worker is pinned to.
+  pub(super) fn resolve(&self, cpu_sets: &[Option<Vec<usize>>]) -> Result<Vec<usize>> {
+    let n_threads = cpu_sets.len();
+
+    let groups = match self {
+      WorkerGroups::Explicit(groups) => {
+        let mut worker_groups = vec![None; n_threads];
+        for (group, workers) in groups.iter().enumerate() {
+          for &worker in workers {
+            if worker >= n_threads {
+              anyhow::bail!(
+                "worker {worker} in group {group} is out of range for {n_threads} threads"
+              );
+            }
+            if worker_groups[worker].replace(group).is_some() {
+              anyhow::bail!("worker {worker} is listed in more than one group");
+            }
+          }
+        }
+
+        let mut next_group = groups.len();
+        worker_groups
+          .into_iter()
+          .map(|group| {
+            group.unwrap_or_else(|| {
+              next_group += 1;
+              next_group - 1
+            })
+          })
+          .collect()
+      }
+      WorkerGroups::Package | WorkerGroups::LastLevelCache => {
+        let mut domains = Vec::new();
+        let mut worker_groups = Vec::with_capacity(n_threads);
+        for (index, cpus) in cpu_sets.iter().enumerate() {
+          let Some(&cpu) = cpus.as_ref().and_then(|cpus| cpus.first()) else {
+            anyhow::bail!(
+              "grouping worker {index} by {self:?} requires pinning it with pin_to_cores"
+            );
+          };
+          let domain = self.cpu_domain(cpu)?;
+          let group = match domains.iter().position(|d| *d == domain) {
+            Some(group) => group,
+            None => {
+              domains.push(domain);
+              domains.len() - 1
+            }
+          };
+          worker_groups.push(group);
+        }
+        worker_groups
+      }
+    };
+
+    Ok(groups)
+  }
+
+  /// Reads the identifier of the package or cache domain `cpu` belongs to.
+  fn cpu_domain(&self, cpu: usize) -> Result<String> {
+    let cpu_dir = Path::new(SYSFS_CPU).join(format!("cpu{cpu}"));
+
+    let path = match self {
+      WorkerGroups::Package => cpu_dir.join("topology/physical_package_id"),
+      _ => last_level_cache(&cpu_dir)?.join("shared_cpu_list"),
+    };
+
+    let domain = fs::read_to_string(&path)
+      .with_context(|| format!("failed to read CPU topology from {}", path.display()))?;
+    Ok(domain.trim().to_string())
+  }
+}
+
+/// Finds the `cache/indexN` directory of the highest cache level of a CPU.
+fn last_level_cache(cpu_dir: &Path) -> Result<PathBuf> {
+  let cache_dir = cpu_dir.join("cache");
+  let entries = fs::read_dir(&cache_dir)
+    .with_context(|| format!("failed to read CPU caches from {}", cache_dir.display()))?;
+
+  let mut best = None;
+  for entry in entries {
+    let path = entry?.path();
+    let is_index = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .is_some_and(|name| name.starts_with("index"));
+    if !is_index {
+      continue;
+    }
+
+    let Ok(level) = fs::read_to_string(path.join("level")) else {
+      continue;
+    };
+    let Ok(level) = level.trim().parse::<u32>() else {
+      continue;
+    };
+    if best
+      .as_ref()
+      .is_none_or(|(best_level, _)| level > *best_level)
+    {
+      best = Some((level, path));
+    }
+  }
+
+  best
+    .map(|(_, path)| path)
+    .with_context(|| format!("no CPU caches found in {}", cache_dir.display()))
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use super::blocking::block_in_place;
+use super::latch::Latch;
+use super::latch::LockLatch;
+use super::latch::SpinLatch;
+use super::worker::WorkerThread;
+
+/// A queue of threads waiting for some condition, used to build blocking
+/// primitives that keep worker threads busy while they wait.
+///
+/// A waiting worker thread keeps executing jobs from the pool until it is
+/// notified, the same way it does while waiting for the other half of a
+/// `join_context`. Other threads simply block, and so does a worker that
+/// waits again from one of those jobs, with a spare thread standing in for
+/// it as in `block_in_place`: otherwise, every contended wait could pile
+/// another stolen job onto its stack.
+pub(crate) struct WaitQueue {
+  waiters: Mutex<VecDeque<Waiter>>,
+
+  /// Number of threads in `waiters` or about to check their condition, so
+  /// that `notify_all` can skip the lock when nobody waits.
+  num_waiters: AtomicUsize,
+}
+
+/// A latch that some waiting thread is blocked on.
+struct Waiter {
+  latch: *const (),
+  set_fn: unsafe fn(*const ()),
+}
+
+unsafe impl Send for Waiter {}
+
+impl Waiter {
+  fn new<L: Latch>(latch: &L) -> Waiter {
+    Waiter {
+      latch: latch as *const L as *const (),
+      set_fn: set_latch::<L>,
+    }
+  }
+
+  /// Wakes the waiting thread; the latch may be gone once this returns.
+  unsafe fn wake(self) {
+    unsafe { (self.set_fn)(self.latch) }
+  }
+}
+
+unsafe fn set_latch<L: Latch>(latch: *const ()) {
+  unsafe { L::set(latch as *const L) }
+}
+
+impl WaitQueue {
+  pub(crate) fn new() -> WaitQueue {
+    WaitQueue {
+      waiters: Mutex::new(VecDeque::new()),
+      num_waiters: AtomicUsize::new(0),
+    }
+  }
+
+  /// Blocks until notified, unless `ready` returns `true`.
+  ///
+  /// `ready` is checked while holding the queue lock, so a notification
+  /// sent after the condition it checks became true cannot be missed.
+  pub(crate) fn wait(&self, ready: impl FnOnce() -> bool) {
+    let worker_thread = unsafe { WorkerThread::current().as_ref() };
+    match worker_thread {
+      Some(worker_thread) if worker_thread.set_waiting(true) => {
+        let latch = SpinLatch::new(worker_thread);
+        if self.enqueue(&latch, ready) {
+          unsafe { worker_thread.wait_until(&latch) };
+        }
+        worker_thread.set_waiting(false);
+      }
+      Some(_) => {
+        let latch = LockLatch::new();
+        if self.enqueue(&latch, ready) {
+          block_in_place(|| latch.wait_and_reset());
+        }
+      }
+      None => {
+        let latch = LockLatch::new();
+        if self.enqueue(&latch, ready) {
+          latch.wait_and_reset();
+        }
+      }
+    }
+  }
+
+  /// Adds `latch` to the queue unless `ready` returns `true`; returns
+  /// whether it was added.
+  fn enqueue<L: Latch>(&self, latch: &L, ready: impl FnOnce() -> bool) -> bool {
+    let mut waiters = self.waiters.lock().unwrap();
+    // Announce ourselves before checking `ready`; pairs with the fence in
+    // `notify_all`, so either we see the condition or the notifier sees us.
+    self.num_waiters.fetch_add(1, Ordering::Relaxed);
+    atomic::fence(Ordering::SeqCst);
+    if ready() {
+      self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+      return false;
+    }
+    waiters.push_back(Waiter::new(latch));
+    true
+  }
+
+  /// Wakes every waiting thread. The condition the waiters check must have
+  /// been made true before calling this.
+  pub(crate) fn notify_all(&self) {
+    atomic::fence(Ordering::SeqCst);
+    if self.num_waiters.load(Ordering::Relaxed) == 0 {
+      return;
+    }
+
+    let waiters = {
+      let mut waiters = self.waiters.lock().unwrap();
+      self.num_waiters.fetch_sub(waiters.len(), Ordering::Relaxed);
+      mem::take(&mut *waiters)
+    };
+    for waiter in waiters {
+      unsafe { waiter.wake() };
+    }
+  }
+}
@@ -2,17 +2,21 @@ use std::cell::Cell;
 use std::mem;
 use std::ptr;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::thread;
 
 use crossbeam_deque::Steal;
 use crossbeam_deque::Stealer;
 use crossbeam_deque::Worker;
 
+use super::affinity;
+use super::heartbeat::JoinMode;
 use super::job::JobRef;
 use super::latch::AsCoreLatch;
 use super::latch::CoreLatch;
 use super::latch::Latch;
 use super::registry::Registry;
+use super::registry::ThreadInfo;
 use super::unwind;
 
 thread_local! {
@@ -26,6 +30,26 @@ pub struct WorkerThread {
 
   index: usize,
 
+  /// Second halves of the joins this worker is running that have not been
+  /// made stealable yet, newest on top. Idle workers promote the oldest
+  /// ones through the stealer in `ThreadInfo::pending`. Only used in
+  /// `JoinMode::Heartbeat`.
+  pending: Worker<JobRef>,
+
+  /// Nesting depth of the `join_context` closure this worker is running,
+  /// 0 outside of any.
+  join_depth: Cell<usize>,
+
+  /// Number of `join_context` calls currently on this worker's stack,
+  /// including those of unrelated jobs run while waiting.
+  join_nesting: Cell<usize>,
+
+  /// Set while the worker runs a `block_in_place` closure.
+  blocked: Cell<bool>,
+
+  /// Set while the worker runs jobs waiting on a `WaitQueue`.
+  waiting: Cell<bool>,
+
   pub registry: Arc<Registry>,
 }
 
@@ -40,21 +64,46 @@ impl Drop for WorkerThread {
 }
 
 impl WorkerThread {
-  pub fn new(worker: Worker<JobRef>, registry: Arc<Registry>, index: usize) -> Self {
+  pub fn new(
+    worker: Worker<JobRef>,
+    pending: Worker<JobRef>,
+    registry: Arc<Registry>,
+    index: usize,
+  ) -> Self {
     Self {
       stealer: worker.stealer(),
       worker,
       registry: registry,
       index: index,
+      pending,
+      join_depth: Cell::new(0),
+      join_nesting: Cell::new(0),
+      blocked: Cell::new(false),
+      waiting: Cell::new(false),
     }
   }
 
   pub fn spawn(self) -> anyhow::Result<()> {
-    let mut thread = thread::Builder::new();
+    let thread = self.thread_builder();
     thread.spawn(|| unsafe { main_loop(self) })?;
     Ok(())
   }
 
+  /// Spawns the spare thread of a blocked worker, see `SpareThread`.
+  pub(super) fn spawn_spare(self) -> anyhow::Result<()> {
+    let thread = self.thread_builder();
+    thread.spawn(|| unsafe { spare_loop(self) })?;
+    Ok(())
+  }
+
+  fn thread_builder(&self) -> thread::Builder {
+    let mut thread = thread::Builder::new();
+    if let Some(stack_size) = self.registry.stack_size() {
+      thread = thread.stack_size(stack_size);
+    }
+    thread
+  }
+
   /// Gets the `WorkerThread` index for the current thread; returns
   /// NULL if this is not a worker thread. This pointer is valid
   /// anywhere on the current thread.
@@ -78,12 +127,104 @@ impl WorkerThread {
     self.index
   }
 
+  pub(super) fn join_depth(&self) -> usize {
+    self.join_depth.get()
+  }
+
+  /// Runs `op` with the join depth of this worker set to `depth`, and
+  /// restores the previous one afterwards, even if `op` panics.
+  pub(super) fn with_join_depth<R>(&self, depth: usize, op: impl FnOnce() -> R) -> R {
+    struct RestoreDepth<'w> {
+      join_depth: &'w Cell<usize>,
+      previous: usize,
+    }
+
+    impl Drop for RestoreDepth<'_> {
+      fn drop(&mut self) {
+        self.join_depth.set(self.previous);
+      }
+    }
+
+    let _restore = RestoreDepth {
+      join_depth: &self.join_depth,
+      previous: self.join_depth.replace(depth),
+    };
+    op()
+  }
+
+  /// Returns `true` if `join_context` may not nest any further on this
+  /// worker, see `ThreadPoolBuilder::max_join_nesting`.
+  pub(super) fn join_nesting_exceeded(&self) -> bool {
+    self
+      .registry
+      .max_join_nesting()
+      .is_some_and(|max| self.join_nesting.get() >= max)
+  }
+
+  /// Counts a `join_context` call on this worker's stack until the
+  /// returned guard is dropped.
+  pub(super) fn enter_join(&self) -> JoinNestingGuard<'_> {
+    self.join_nesting.set(self.join_nesting.get() + 1);
+    JoinNestingGuard {
+      join_nesting: &self.join_nesting,
+    }
+  }
+
   pub unsafe fn push(&self, job: JobRef) {
     let queue_was_empty = self.worker.is_empty();
     self.worker.push(job);
     self.registry.sleep.new_internal_jobs(1, queue_was_empty);
   }
 
+  /// Records `job` as pending instead of pushing it; an idle worker may
+  /// promote it at the next heartbeat.
+  pub(super) fn push_pending(&self, job: JobRef) {
+    self.pending.push(job);
+  }
+
+  /// Pushes every pending job, oldest first, so that other workers can
+  /// steal them.
+  pub(super) fn promote_pending(&self) {
+    let pending = &self.registry.thread_infos[self.index].pending;
+    loop {
+      match pending.steal() {
+        Steal::Success(job) => unsafe { self.push(job) },
+        Steal::Empty => return,
+        Steal::Retry => {}
+      }
+    }
+  }
+
+  /// Marks the worker as blocked, returning `false` if it already was.
+  pub(super) fn set_blocked(&self, blocked: bool) -> bool {
+    self.blocked.replace(blocked) != blocked
+  }
+
+  /// Marks the worker as waiting on a `WaitQueue`, returning `false` if it
+  /// already was.
+  pub(super) fn set_waiting(&self, waiting: bool) -> bool {
+    self.waiting.replace(waiting) != waiting
+  }
+
+  /// Takes `job` back if it is still pending, i.e. it was never promoted
+  /// and must be run by the caller.
+  pub(super) fn reclaim_pending(&self, job: &JobRef) -> bool {
+    // `job` is the newest pending job if it is still there. If it is not,
+    // it was promoted or run while this worker waited for another join,
+    // and the top one belongs to an enclosing join: put it back.
+    match self.pending.pop() {
+      Some(top) if top == *job => true,
+      Some(top) => {
+        self.pending.push(top);
+        false
+      }
+      None => false,
+    }
+  }
+
+  /// Pops a job off this worker's deque, or else takes its newest pending
+  /// join: a worker waiting for a promoted join runs its own pending ones
+  /// first, like it pops its own deque in `JoinMode::Eager`.
   pub(super) fn take_local_job(&self) -> Option<JobRef> {
     let popped_job = self.worker.pop();
 
@@ -94,7 +235,7 @@ impl WorkerThread {
     loop {
       match self.stealer.steal() {
         Steal::Success(job) => return Some(job),
-        Steal::Empty => return None,
+        Steal::Empty => return self.pending.pop(),
         Steal::Retry => {}
       }
     }
@@ -152,6 +293,28 @@ impl WorkerThread {
       .take_local_job()
       .or_else(|| self.steal())
       .or_else(|| self.registry.pop_injected_job())
+      .or_else(|| self.steal_pending())
+  }
+
+  /// Takes the oldest pending join of another worker whose heartbeat is
+  /// due, see `JoinMode::Heartbeat`.
+  fn steal_pending(&self) -> Option<JobRef> {
+    if self.registry.join_mode() == JoinMode::Eager {
+      return None;
+    }
+
+    let thread_infos = &self.registry.thread_infos;
+    (0..thread_infos.len())
+      .filter(|&i| i != self.index)
+      .find_map(|victim_index| {
+        let victim = &thread_infos[victim_index];
+        if victim.pending.is_empty() || !victim.heartbeat.swap(false, Ordering::Relaxed) {
+          return None;
+        }
+        // On `Retry`, the victim is reclaiming its last pending join or
+        // another worker is promoting one: wait for the next heartbeat.
+        victim.pending.steal().success()
+      })
   }
 
   fn steal(&self) -> Option<JobRef> {
@@ -161,21 +324,28 @@ impl WorkerThread {
       return None;
     }
 
+    // Try the workers of our own group before crossing into other groups.
+    let group = thread_infos[self.index].group;
+    let victims = || {
+      let near =
+        (0..num_threads).filter(move |&i| i != self.index && thread_infos[i].group == group);
+      let far = (0..num_threads).filter(move |&i| thread_infos[i].group != group);
+      near.chain(far)
+    };
+
     loop {
       let mut retry = false;
-      let job = (0..num_threads)
-        .filter(|&i| i != self.index)
-        .find_map(|victim_index| {
-          let victim = &thread_infos[victim_index];
-          match victim.stealer.steal() {
-            Steal::Success(job) => Some(job),
-            Steal::Empty => None,
-            Steal::Retry => {
-              retry = true;
-              None
-            }
+      let job = victims().find_map(|victim_index| {
+        let victim = &thread_infos[victim_index];
+        match victim.stealer.steal() {
+          Steal::Success(job) => Some(job),
+          Steal::Empty => None,
+          Steal::Retry => {
+            retry = true;
+            None
           }
-        });
+        }
+      });
       if job.is_some() || !retry {
         return job;
       }
@@ -183,7 +353,9 @@ impl WorkerThread {
   }
 
   pub unsafe fn execute(&self, job: JobRef) {
-    job.execute();
+    // The job is unrelated to whatever this worker was doing, so it starts
+    // outside of any join; the second half of a join sets its own depth.
+    self.with_join_depth(0, || job.execute());
   }
 
   unsafe fn wait_until_out_of_work(&self) {
@@ -199,12 +371,35 @@ impl WorkerThread {
   }
 }
 
+pub(super) struct JoinNestingGuard<'w> {
+  join_nesting: &'w Cell<usize>,
+}
+
+impl Drop for JoinNestingGuard<'_> {
+  fn drop(&mut self) {
+    self.join_nesting.set(self.join_nesting.get() - 1);
+  }
+}
+
+/// Pins the current thread to the CPUs of `info`, if any, keeping the
+/// error for `Registry::check_pinned`.
+fn pin_current_thread(info: &ThreadInfo) {
+  if let Some(cpus) = &info.cpus
+    && let Err(err) = affinity::pin_current_thread(cpus)
+  {
+    *info.pin_error.lock().unwrap() = Some(err);
+  }
+}
+
 unsafe fn main_loop(worker: WorkerThread) {
   WorkerThread::set_current(&worker);
   let registry = &*worker.registry;
   let index = worker.index;
+  let info = &registry.thread_infos[index];
+
+  pin_current_thread(info);
 
-  Latch::set(&registry.thread_infos[index].primed);
+  Latch::set(&info.primed);
 
   let abort_guard = unwind::AbortIfPanic;
 
@@ -212,3 +407,23 @@ unsafe fn main_loop(worker: WorkerThread) {
 
   mem::forget(abort_guard);
 }
+
+/// Runs the pool's jobs like a worker while the worker this thread stands
+/// in for is blocked, and waits for it to block otherwise.
+unsafe fn spare_loop(worker: WorkerThread) {
+  unsafe { WorkerThread::set_current(&worker) };
+  let registry = &*worker.registry;
+  let index = worker.index;
+  let (_, spare) = registry
+    .spare_thread(index - registry.num_threads())
+    .unwrap();
+
+  pin_current_thread(&registry.thread_infos[index]);
+
+  let _abort_guard = unwind::AbortIfPanic;
+
+  loop {
+    let release = spare.wait_until_needed();
+    unsafe { worker.wait_until(&*release) };
+  }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::vec;
+
+use crossbeam_utils::CachePadded;
+
+use super::registry::Registry;
+use super::worker::WorkerThread;
+
+/// Holds one value of `T` per worker thread of a pool, e.g. a per-thread
+/// accumulator that is merged once the parallel work is done.
+///
+/// [`get`](WorkerLocal::get) returns the value of the calling worker,
+/// looked up by its index, so it is cheaper than a `thread_local!` and
+/// the values can be collected afterwards with `into_iter`.
+pub struct WorkerLocal<T> {
+  /// Padded so that the values of different workers do not share cache
+  /// lines.
+  locals: Vec<CachePadded<T>>,
+  registry: Arc<Registry>,
+}
+
+/// Each worker only ever gets a shared reference to its own value, and
+/// such a reference cannot leave the worker unless `T: Sync`.
+unsafe impl<T: Send> Sync for WorkerLocal<T> {}
+
+impl<T> WorkerLocal<T> {
+  /// Creates one value per worker of the current pool by calling `init`
+  /// with the index of each worker. The spare threads that stand in for
+  /// workers blocked in [`block_in_place`](super::block_in_place) get
+  /// values of their own, after those of the workers.
+  pub fn new<F>(mut init: F) -> WorkerLocal<T>
+  where F: FnMut(usize) -> T {
+    let registry = Registry::current();
+    WorkerLocal {
+      locals: (0..registry.thread_infos.len())
+        .map(|index| CachePadded::new(init(index)))
+        .collect(),
+      registry,
+    }
+  }
+
+  /// Returns the value of the current worker thread.
+  ///
+  /// # Panics
+  ///
+  /// Panics if called from outside the pool this was created for.
+  pub fn get(&self) -> &T {
+    let worker_thread = unsafe { WorkerThread::current().as_ref() };
+    match worker_thread {
+      Some(worker_thread) if Arc::ptr_eq(worker_thread.registry(), &self.registry) => {
+        &self.locals[worker_thread.index()]
+      }
+      _ => panic!("WorkerLocal can only be used on the thread pool it was created for"),
+    }
+  }
+}
+
+impl<T> IntoIterator for WorkerLocal<T> {
+  type Item = T;
+  type IntoIter = IntoIter<T>;
+
+  /// Returns the values of all workers, in worker index order.
+  fn into_iter(self) -> IntoIter<T> {
+    IntoIter {
+      locals: self.locals.into_iter(),
+    }
+  }
+}
+
+/// Iterator over the values of a [`WorkerLocal`], by worker index.
+pub struct IntoIter<T> {
+  locals: vec::IntoIter<CachePadded<T>>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+  type Item = T;
+
+  fn next(&mut self) -> Option<T> {
+    self.locals.next().map(CachePadded::into_inner)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.locals.size_hint()
+  }
+}
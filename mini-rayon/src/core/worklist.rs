@@ -0,0 +1,156 @@
+use std::marker::PhantomData;
+
+use super::job::HeapJob;
+use super::latch::CountLatch;
+use super::latch::Latch;
+use super::registry::in_worker;
+use super::unwind;
+use super::worker::WorkerThread;
+
+/// Processes `initial` and every item pushed while processing, in parallel,
+/// and returns once no items are left.
+///
+/// `op` receives each item along with a [`Pusher`] it can use to add more
+/// items. Pushed items go onto the deque of the worker running `op`, so
+/// they stay local unless other workers run out of work and steal them.
+/// This fits parallel graph traversals, crawlers and fixpoint solvers.
+///
+/// If `op` panics, items that have not started yet are skipped and the
+/// panic is propagated once the running ones finish.
+pub fn worklist<T, I, F>(initial: I, op: F)
+where
+  T: Send,
+  I: IntoIterator<Item = T>,
+  F: Fn(T, &Pusher<'_, T>) + Sync + Send,
+{
+  let initial: Vec<T> = initial.into_iter().collect();
+
+  in_worker(|worker_thread, _| unsafe {
+    let worklist_run = WorklistRun {
+      op,
+      // Hold one count for ourselves until all initial items are in.
+      outstanding: CountLatch::new(worker_thread, 1),
+      _marker: PhantomData,
+    };
+
+    for item in initial {
+      worklist_run.push(worker_thread, item);
+    }
+    Latch::set(&worklist_run.outstanding);
+
+    worklist_run.outstanding.wait(worker_thread);
+  })
+}
+
+/// Adds items to a running [`worklist`].
+pub struct Pusher<'w, T> {
+  worklist_run: &'w dyn PushItem<T>,
+}
+
+impl<T> Pusher<'_, T> {
+  /// Queues `item` on the current worker's deque.
+  pub fn push(&self, item: T) {
+    self.worklist_run.push_item(item);
+  }
+}
+
+/// Erases the operation type from `Pusher`.
+trait PushItem<T> {
+  fn push_item(&self, item: T);
+}
+
+struct WorklistRun<'r, T, F> {
+  op: F,
+
+  /// Counts the items that have been pushed but not processed yet.
+  outstanding: CountLatch<'r>,
+
+  _marker: PhantomData<fn(T)>,
+}
+
+impl<T, F> WorklistRun<'_, T, F>
+where
+  T: Send,
+  F: Fn(T, &Pusher<'_, T>) + Sync,
+{
+  unsafe fn push(&self, worker_thread: &WorkerThread, item: T) {
+    self.outstanding.increment();
+    unsafe {
+      let job = HeapJob::new(move || self.execute(item));
+      worker_thread.push(job.into_job_ref());
+    }
+  }
+
+  fn execute(&self, item: T) {
+    if !self.outstanding.panicked() {
+      let pusher = Pusher { worklist_run: self };
+      if let Err(err) = unwind::halt_unwinding(|| (self.op)(item, &pusher)) {
+        self.outstanding.record_panic(err);
+      }
+    }
+    unsafe { Latch::set(&self.outstanding) };
+  }
+}
+
+impl<T, F> PushItem<T> for WorklistRun<'_, T, F>
+where
+  T: Send,
+  F: Fn(T, &Pusher<'_, T>) + Sync,
+{
+  fn push_item(&self, item: T) {
+    unsafe {
+      let worker_thread = &*WorkerThread::current();
+      self.push(worker_thread, item);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::panic;
+  use std::sync::atomic::AtomicU64;
+  use std::sync::atomic::AtomicUsize;
+  use std::sync::atomic::Ordering;
+
+  use super::worklist;
+
+  #[test]
+  fn pushed_items_are_all_processed() {
+    let count = AtomicUsize::new(0);
+    let sum = AtomicU64::new(0);
+    worklist([1u64], |node, pusher| {
+      count.fetch_add(1, Ordering::Relaxed);
+      sum.fetch_add(node, Ordering::Relaxed);
+      if node < 1 << 14 {
+        pusher.push(2 * node);
+        pusher.push(2 * node + 1);
+      }
+    });
+    // Every node of a complete binary tree with 2^15 - 1 nodes, numbered
+    // in breadth-first order from 1.
+    let num_nodes = (1 << 15) - 1;
+    assert_eq!(count.into_inner(), num_nodes as usize);
+    assert_eq!(sum.into_inner(), num_nodes * (num_nodes + 1) / 2);
+  }
+
+  #[test]
+  fn empty_worklist_returns() {
+    worklist(Vec::<u32>::new(), |_, _| unreachable!());
+  }
+
+  #[test]
+  fn panicking_item_is_propagated() {
+    let result = panic::catch_unwind(|| {
+      worklist(0..100u32, |item, pusher| {
+        if item == 500 {
+          panic!("item failed");
+        }
+        if item < 1000 {
+          pusher.push(item + 100);
+        }
+      })
+    });
+    let err = result.unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"item failed"));
+  }
+}
@@ -0,0 +1,234 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::iter::ParallelIterator;
+use crate::plumbing::Consumer;
+use crate::plumbing::Folder;
+use crate::plumbing::Producer;
+use crate::plumbing::ProducerCallback;
+
+/// A flag shared between a parallel iterator and whoever may want to stop
+/// it early, see [`ParallelIterator::with_cancellation`].
+///
+/// Cancellation is cooperative: items that are already being processed run
+/// to completion, but no new items are started once the token is cancelled.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+  cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+  pub fn new() -> CancellationToken {
+    CancellationToken::default()
+  }
+
+  /// Cancels the token, and with it every iterator it is attached to.
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+/// `Cancellable` is an iterator that stops producing items once its
+/// [`CancellationToken`] is cancelled.
+///
+/// This struct is created by [`ParallelIterator::with_cancellation`].
+pub struct Cancellable<I> {
+  base: I,
+  token: CancellationToken,
+}
+
+impl<I> Cancellable<I> {
+  pub(crate) fn new(base: I, token: CancellationToken) -> Self {
+    Cancellable { base, token }
+  }
+}
+
+impl<I: ParallelIterator> ParallelIterator for Cancellable<I> {
+  type Item = I::Item;
+
+  fn len(&self) -> usize {
+    self.base.len()
+  }
+
+  fn drive<C>(self, consumer: C) -> C::Result
+  where C: Consumer<Self::Item> {
+    let consumer = CancelConsumer {
+      base: consumer,
+      token: &self.token,
+    };
+    self.base.drive(consumer)
+  }
+
+  fn with_producer<CB>(self, callback: CB) -> CB::Output
+  where CB: ProducerCallback<Self::Item> {
+    return self.base.with_producer(Callback {
+      callback,
+      token: &self.token,
+    });
+
+    struct Callback<'t, CB> {
+      callback: CB,
+      token: &'t CancellationToken,
+    }
+
+    impl<T, CB> ProducerCallback<T> for Callback<'_, CB>
+    where CB: ProducerCallback<T>
+    {
+      type Output = CB::Output;
+
+      fn callback<P>(self, base: P) -> CB::Output
+      where P: Producer<Item = T> {
+        let producer = CancelProducer {
+          base,
+          token: self.token,
+        };
+        self.callback.callback(producer)
+      }
+    }
+  }
+}
+
+struct CancelProducer<'t, P> {
+  base: P,
+  token: &'t CancellationToken,
+}
+
+impl<'t, P: Producer> Producer for CancelProducer<'t, P> {
+  type Item = P::Item;
+  type IntoIter = CancelIter<'t, P::IntoIter>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    CancelIter {
+      base: self.base.into_iter(),
+      token: self.token,
+    }
+  }
+
+  fn split_at(self, index: usize) -> (Self, Self) {
+    let (left, right) = self.base.split_at(index);
+    (
+      CancelProducer {
+        base: left,
+        token: self.token,
+      },
+      CancelProducer {
+        base: right,
+        token: self.token,
+      },
+    )
+  }
+
+  fn min_len(&self) -> usize {
+    self.base.min_len()
+  }
+
+  fn max_len(&self) -> usize {
+    self.base.max_len()
+  }
+
+  fn fold_with<F>(self, folder: F) -> F
+  where F: Folder<Self::Item> {
+    let folder = CancelFolder {
+      base: folder,
+      token: self.token,
+    };
+    self.base.fold_with(folder).base
+  }
+}
+
+struct CancelIter<'t, I> {
+  base: I,
+  token: &'t CancellationToken,
+}
+
+impl<I: Iterator> Iterator for CancelIter<'_, I> {
+  type Item = I::Item;
+
+  fn next(&mut self) -> Option<I::Item> {
+    if self.token.is_cancelled() {
+      None
+    } else {
+      self.base.next()
+    }
+  }
+}
+
+struct CancelConsumer<'t, C> {
+  base: C,
+  token: &'t CancellationToken,
+}
+
+impl<'t, T, C> Consumer<T> for CancelConsumer<'t, C>
+where C: Consumer<T>
+{
+  type Result = C::Result;
+  type Reducer = C::Reducer;
+  type Folder = CancelFolder<'t, C::Folder>;
+
+  fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+    let (left, right, reducer) = self.base.split_at(index);
+    (
+      CancelConsumer {
+        base: left,
+        token: self.token,
+      },
+      CancelConsumer {
+        base: right,
+        token: self.token,
+      },
+      reducer,
+    )
+  }
+
+  fn full(&self) -> bool {
+    self.token.is_cancelled() || self.base.full()
+  }
+
+  fn into_folder(self) -> Self::Folder {
+    CancelFolder {
+      base: self.base.into_folder(),
+      token: self.token,
+    }
+  }
+}
+
+struct CancelFolder<'t, F> {
+  base: F,
+  token: &'t CancellationToken,
+}
+
+impl<T, F> Folder<T> for CancelFolder<'_, F>
+where F: Folder<T>
+{
+  type Result = F::Result;
+
+  fn consume(mut self, item: T) -> Self {
+    self.base = self.base.consume(item);
+    self
+  }
+
+  fn consume_iter<I>(mut self, iter: I) -> Self
+  where I: IntoIterator<Item = T> {
+    // Don't rely on the base folder checking `full` between items, it may
+    // well consume the whole iterator in one go.
+    let iter = CancelIter {
+      base: iter.into_iter(),
+      token: self.token,
+    };
+    self.base = self.base.consume_iter(iter);
+    self
+  }
+
+  fn complete(self) -> F::Result {
+    self.base.complete()
+  }
+
+  fn full(&self) -> bool {
+    self.token.is_cancelled() || self.base.full()
+  }
+}
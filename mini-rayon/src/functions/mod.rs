@@ -1,4 +1,11 @@
+mod cancel;
 mod for_each;
 mod noop;
+mod panics;
 
+pub use cancel::Cancellable;
+pub use cancel::CancellationToken;
 pub use for_each::for_each;
+pub use panics::CaughtPanic;
+pub use panics::MultiPanic;
+pub use panics::for_each_collect_panics;
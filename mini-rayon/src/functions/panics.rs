@@ -0,0 +1,95 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::core::current_thread_index;
+use crate::core::halt_unwinding;
+use crate::for_each;
+use crate::iter::ParallelIterator;
+
+/// A panic caught while running one item of a parallel operation.
+pub struct CaughtPanic {
+  /// Index of the worker thread the item ran on, `None` if it ran outside
+  /// the pool.
+  pub worker_index: Option<usize>,
+  pub payload: Box<dyn Any + Send>,
+}
+
+impl CaughtPanic {
+  /// The panic message, if the payload is a string as produced by
+  /// `panic!`.
+  pub fn message(&self) -> Option<&str> {
+    if let Some(message) = self.payload.downcast_ref::<&str>() {
+      Some(message)
+    } else {
+      self.payload.downcast_ref::<String>().map(String::as_str)
+    }
+  }
+}
+
+impl fmt::Debug for CaughtPanic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("CaughtPanic")
+      .field("worker_index", &self.worker_index)
+      .field("message", &self.message())
+      .finish()
+  }
+}
+
+/// Every panic raised by a parallel operation, in the order they were
+/// caught, see [`ParallelIterator::for_each_collect_panics`].
+#[derive(Debug)]
+pub struct MultiPanic {
+  panics: Vec<CaughtPanic>,
+}
+
+impl MultiPanic {
+  pub fn count(&self) -> usize {
+    self.panics.len()
+  }
+
+  pub fn panics(&self) -> &[CaughtPanic] {
+    &self.panics
+  }
+
+  pub fn into_panics(self) -> Vec<CaughtPanic> {
+    self.panics
+  }
+}
+
+impl fmt::Display for MultiPanic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} parallel tasks panicked", self.count())?;
+    if let Some(message) = self.panics.first().and_then(CaughtPanic::message) {
+      write!(f, ", first with: {message}")?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for MultiPanic {}
+
+pub fn for_each_collect_panics<Iter, F, T>(pi: Iter, op: &F) -> Result<(), MultiPanic>
+where
+  Iter: ParallelIterator<Item = T>,
+  F: Fn(T) + Sync,
+  T: Send,
+{
+  let panics = Mutex::new(Vec::new());
+  for_each(pi, &|item| {
+    if let Err(payload) = halt_unwinding(|| op(item)) {
+      panics.lock().unwrap().push(CaughtPanic {
+        worker_index: current_thread_index(),
+        payload,
+      });
+    }
+  });
+
+  let panics = panics.into_inner().unwrap();
+  if panics.is_empty() {
+    Ok(())
+  } else {
+    Err(MultiPanic { panics })
+  }
+}
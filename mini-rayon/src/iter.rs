@@ -1,6 +1,10 @@
 use std::ops::RangeBounds;
 
 use crate::for_each;
+use crate::for_each_collect_panics;
+use crate::functions::Cancellable;
+use crate::functions::CancellationToken;
+use crate::functions::MultiPanic;
 use crate::plumbing::Consumer;
 use crate::plumbing::ProducerCallback;
 
@@ -19,6 +23,21 @@ pub trait ParallelIterator: Sized + Send {
     for_each(self, &op);
   }
 
+  /// Like `for_each`, but a panicking item does not stop the others: every
+  /// item is processed, and the panics are returned together instead of
+  /// propagating only the first one.
+  fn for_each_collect_panics<Op>(self, op: Op) -> Result<(), MultiPanic>
+  where Op: Fn(Self::Item) + Sync + Send {
+    for_each_collect_panics(self, &op)
+  }
+
+  /// Stops the iterator once `token` is cancelled: items that have not
+  /// been started yet are skipped, and the remaining work is no longer
+  /// split across threads.
+  fn with_cancellation(self, token: CancellationToken) -> Cancellable<Self> {
+    Cancellable::new(self, token)
+  }
+
   fn len(&self) -> usize;
 
   /// Internal method used to define the behavior of this parallel
@@ -5,5 +5,30 @@ mod iter;
 mod math;
 mod plumbing;
 pub mod prelude;
+pub mod sync;
 
 pub(crate) use functions::for_each;
+pub(crate) use functions::for_each_collect_panics;
+
+pub use crate::core::CoreSelection;
+pub use crate::core::FnContext;
+pub use crate::core::JoinMode;
+pub use crate::core::Pipeline;
+pub use crate::core::Pusher;
+pub use crate::core::TaskGraph;
+pub use crate::core::TaskId;
+pub use crate::core::ThreadPoolBuilder;
+pub use crate::core::WorkerGroups;
+pub use crate::core::WorkerLocal;
+pub use crate::core::block_in_place;
+pub use crate::core::current_thread_index;
+pub use crate::core::join_all;
+pub use crate::core::join_context;
+pub use crate::core::join3;
+pub use crate::core::try_join;
+pub use crate::core::worklist;
+pub use crate::functions::Cancellable;
+pub use crate::functions::CancellationToken;
+pub use crate::functions::CaughtPanic;
+pub use crate::functions::MultiPanic;
+pub use crate::plumbing::divide_and_conquer;
@@ -211,3 +211,65 @@ where
     }
   }
 }
+
+/// Solves `problem` by splitting it in two with `split` and solving the
+/// halves in parallel, then merging their results with `combine`.
+///
+/// Splitting stops once `is_small` returns `true`, and otherwise adapts to
+/// the load the same way iterators do: a problem is split into about as
+/// many parts as there are threads, plus more whenever one of them is
+/// stolen by another thread. The remaining parts are passed to `solve`
+/// whole, so it must handle problems that are not small yet.
+pub fn divide_and_conquer<P, R, IS, SP, SO, CO>(
+  problem: P,
+  is_small: IS,
+  split: SP,
+  solve: SO,
+  combine: CO,
+) -> R
+where
+  P: Send,
+  R: Send,
+  IS: Fn(&P) -> bool + Sync,
+  SP: Fn(P) -> (P, P) + Sync,
+  SO: Fn(P) -> R + Sync,
+  CO: Fn(R, R) -> R + Sync,
+{
+  let ops = DivideAndConquer {
+    is_small,
+    split,
+    solve,
+    combine,
+  };
+  return ops.helper(problem, false, Splitter::new());
+
+  struct DivideAndConquer<IS, SP, SO, CO> {
+    is_small: IS,
+    split: SP,
+    solve: SO,
+    combine: CO,
+  }
+
+  impl<IS, SP, SO, CO> DivideAndConquer<IS, SP, SO, CO> {
+    fn helper<P, R>(&self, problem: P, migrated: bool, mut splitter: Splitter) -> R
+    where
+      P: Send,
+      R: Send,
+      IS: Fn(&P) -> bool + Sync,
+      SP: Fn(P) -> (P, P) + Sync,
+      SO: Fn(P) -> R + Sync,
+      CO: Fn(R, R) -> R + Sync,
+    {
+      if !(self.is_small)(&problem) && splitter.try_split(migrated) {
+        let (left, right) = (self.split)(problem);
+        let (left_result, right_result) = join_context(
+          |context| self.helper(left, context.migrated(), splitter),
+          |context| self.helper(right, context.migrated(), splitter),
+        );
+        (self.combine)(left_result, right_result)
+      } else {
+        (self.solve)(problem)
+      }
+    }
+  }
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::core::WaitQueue;
+
+/// A one-shot event that threads can wait on.
+///
+/// Once [`set`](Latch::set), the latch stays set and every current and
+/// future call to [`wait`](Latch::wait) returns immediately.
+pub struct Latch {
+  is_set: AtomicBool,
+  waiters: WaitQueue,
+}
+
+impl Latch {
+  pub fn new() -> Latch {
+    Latch {
+      is_set: AtomicBool::new(false),
+      waiters: WaitQueue::new(),
+    }
+  }
+
+  /// Sets the latch and wakes every thread waiting on it.
+  pub fn set(&self) {
+    self.is_set.store(true, Ordering::Release);
+    self.waiters.notify_all();
+  }
+
+  /// Returns `true` if the latch has been set.
+  pub fn probe(&self) -> bool {
+    self.is_set.load(Ordering::Acquire)
+  }
+
+  /// Blocks until the latch is set. On a worker thread, this executes jobs
+  /// from the pool while waiting.
+  pub fn wait(&self) {
+    while !self.probe() {
+      self.waiters.wait(|| self.probe());
+    }
+  }
+}
+
+impl Default for Latch {
+  fn default() -> Self {
+    Latch::new()
+  }
+}
@@ -0,0 +1,13 @@
+//! Blocking primitives that are aware of the thread pool.
+//!
+//! When a worker thread has to wait on one of these, it keeps executing jobs
+//! from the pool instead of blocking its OS thread, so the work that will
+//! eventually release it can make progress even if it sits in the waiting
+//! worker's own deque.
+
+mod latch;
+mod mutex;
+
+pub use latch::Latch;
+pub use mutex::Mutex;
+pub use mutex::MutexGuard;
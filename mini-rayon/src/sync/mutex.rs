@@ -0,0 +1,151 @@
+use std::cell::UnsafeCell;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::core::WaitQueue;
+
+/// A mutual exclusion lock whose waiters help the pool instead of blocking.
+///
+/// Unlike `std::sync::Mutex`, a worker thread that has to wait for the lock
+/// keeps executing jobs from the pool, so the holder is never stuck behind
+/// work queued on the waiting worker. The lock is not poisoned when a
+/// holder panics.
+pub struct Mutex<T: ?Sized> {
+  locked: AtomicBool,
+  waiters: WaitQueue,
+  data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// Releases the lock of a [`Mutex`] when dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+  mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+  pub fn new(value: T) -> Mutex<T> {
+    Mutex {
+      locked: AtomicBool::new(false),
+      waiters: WaitQueue::new(),
+      data: UnsafeCell::new(value),
+    }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.data.into_inner()
+  }
+}
+
+impl<T: ?Sized> Mutex<T> {
+  /// Acquires the lock, waiting until it is available. On a worker thread,
+  /// this executes jobs from the pool while waiting.
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    loop {
+      if let Some(guard) = self.try_lock() {
+        return guard;
+      }
+
+      let mut acquired = false;
+      self.waiters.wait(|| {
+        acquired = self.try_acquire();
+        acquired
+      });
+      if acquired {
+        return MutexGuard { mutex: self };
+      }
+    }
+  }
+
+  /// Acquires the lock if it is available right now.
+  pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+    self.try_acquire().then(|| MutexGuard { mutex: self })
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.data.get_mut()
+  }
+
+  fn try_acquire(&self) -> bool {
+    self
+      .locked
+      .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_ok()
+  }
+
+  fn unlock(&self) {
+    self.locked.store(false, Ordering::Release);
+    // Wake everybody rather than handing the lock to one waiter: a waiting
+    // worker may be deep inside a stolen job, blocked on another lock, and
+    // unable to come back for this one while others could take it.
+    self.waiters.notify_all();
+  }
+}
+
+impl<T: Default> Default for Mutex<T> {
+  fn default() -> Self {
+    Mutex::new(T::default())
+  }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    // SAFETY: holding the guard means we hold the lock
+    unsafe { &*self.mutex.data.get() }
+  }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    // SAFETY: holding the guard means we hold the lock
+    unsafe { &mut *self.mutex.data.get() }
+  }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+  fn drop(&mut self) {
+    self.mutex.unlock();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread;
+
+  use super::Mutex;
+
+  #[test]
+  fn try_lock_fails_while_locked() {
+    let mutex = Mutex::new(0);
+    let guard = mutex.lock();
+    assert!(mutex.try_lock().is_none());
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+    assert!(mutex.try_lock().is_some());
+  }
+
+  #[test]
+  fn contended_lock_is_exclusive() {
+    let mutex = Mutex::new(0);
+    thread::scope(|s| {
+      for _ in 0..4 {
+        s.spawn(|| {
+          for _ in 0..1000 {
+            let mut guard = mutex.lock();
+            let value = *guard;
+            thread::yield_now();
+            *guard = value + 1;
+          }
+        });
+      }
+    });
+    assert_eq!(mutex.into_inner(), 4000);
+  }
+}